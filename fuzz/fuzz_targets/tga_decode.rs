@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use tinyrender::tga::{DecodeLimits, TGAImage};
+
+fuzz_target!(|data: &[u8]| {
+    let limits = DecodeLimits {
+        max_width: 2048,
+        max_height: 2048,
+    };
+    if let Ok(img) = TGAImage::from_bytes_with_limits(data, limits) {
+        // anything the decoder accepts has to survive an encode/decode round trip
+        let mut encoded = Vec::new();
+        img.write_to(&mut encoded, true).unwrap();
+        TGAImage::from_bytes(&encoded).unwrap();
+    }
+});
@@ -0,0 +1,148 @@
+use crate::tga::{TGAFormat, TGAImage};
+
+/*
+    Floating-point image
+
+    Same layout as TGAImage (rows from the top, interleaved
+    channels) but with one f32 per channel and channels in
+    RGB(A) order. Holds depth buffers and other data that
+    doesn't fit in 8 bits.
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatImage {
+    data: Vec<f32>,
+    width: i32,
+    height: i32,
+    channels: i32,
+}
+
+impl FloatImage {
+    pub fn new_dimensions(w: i32, h: i32, channels: i32) -> Self {
+        Self {
+            data: vec![0.; (w * h * channels) as usize],
+            width: w,
+            height: h,
+            channels,
+        }
+    }
+
+    /// Wraps existing samples, e.g. a depth buffer. Returns None if `data` has the wrong length.
+    pub fn from_data(w: i32, h: i32, channels: i32, data: Vec<f32>) -> Option<Self> {
+        if w < 0 || h < 0 || channels < 1 || data.len() != (w * h * channels) as usize {
+            return None;
+        }
+        Some(Self {
+            data,
+            width: w,
+            height: h,
+            channels,
+        })
+    }
+
+    /// Converts 8-bit channels to [0, 1], reordering BGR(A) to RGB(A).
+    pub fn from_tga(img: &TGAImage) -> Self {
+        let channels = img.get_bytespp();
+        let mut out = FloatImage::new_dimensions(img.get_width(), img.get_height(), channels);
+        if let Some(src) = img.buffer() {
+            for (dst, px) in out
+                .data
+                .chunks_mut(channels as usize)
+                .zip(src.chunks(channels as usize))
+            {
+                for (c, v) in dst.iter_mut().enumerate() {
+                    let from = if c < 3 && channels >= 3 { 2 - c } else { c };
+                    *v = px[from] as f32 / 255.;
+                }
+            }
+        }
+        out
+    }
+
+    /// Converts to 8-bit channels, clamping to [0, 1]. Two channel images keep only the first.
+    pub fn to_tga(&self) -> TGAImage {
+        let bytespp = match self.channels {
+            1 | 2 => TGAFormat::Grayscale,
+            3 => TGAFormat::RGB,
+            _ => TGAFormat::RGBA,
+        } as usize;
+        let mut img = TGAImage::new_dimensions(self.width, self.height, bytespp as i32);
+        let dst = img.buffer_mut().unwrap();
+        for (px, src) in dst
+            .chunks_mut(bytespp)
+            .zip(self.data.chunks(self.channels as usize))
+        {
+            for (c, v) in px.iter_mut().enumerate() {
+                let from = if c < 3 && bytespp >= 3 { 2 - c } else { c };
+                *v = (src[from].clamp(0., 1.) * 255.).round() as u8;
+            }
+        }
+        img
+    }
+
+    pub fn get_width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn get_height(&self) -> i32 {
+        self.height
+    }
+
+    pub fn get_channels(&self) -> i32 {
+        self.channels
+    }
+
+    pub fn buffer(&self) -> &[f32] {
+        &self.data
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [f32] {
+        &mut self.data
+    }
+
+    /// Channels of pixel (x, y), or None outside the image.
+    pub fn get(&self, x: i32, y: i32) -> Option<&[f32]> {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+        let index = ((x + y * self.width) * self.channels) as usize;
+        Some(&self.data[index..index + self.channels as usize])
+    }
+
+    /// Sets the channels of pixel (x, y) from `c`, ignoring pixels outside the image. Channels
+    /// beyond the end of a shorter `c` keep their values, and extra values are ignored.
+    pub fn set(&mut self, x: i32, y: i32, c: &[f32]) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
+        }
+        let n = (self.channels as usize).min(c.len());
+        let index = ((x + y * self.width) * self.channels) as usize;
+        self.data[index..index + n].copy_from_slice(&c[..n]);
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let line = (self.width * self.channels) as usize;
+        for i in 0..self.height as usize / 2 {
+            let j = self.height as usize - 1 - i;
+            let (top, bottom) = self.data.split_at_mut(j * line);
+            top[i * line..(i + 1) * line].swap_with_slice(&mut bottom[..line]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_takes_as_many_channels_as_given() {
+        let mut img = FloatImage::new_dimensions(2, 1, 4);
+        img.set(1, 0, &[1., 2., 3., 4., 5.]);
+        assert_eq!(img.get(1, 0).unwrap(), [1., 2., 3., 4.]);
+        img.set(1, 0, &[9., 8.]);
+        assert_eq!(img.get(1, 0).unwrap(), [9., 8., 3., 4.]);
+        img.set(0, 0, &[]);
+        img.set(2, 0, &[1.]);
+        assert_eq!(img.buffer(), [0., 0., 0., 0., 9., 8., 3., 4.]);
+    }
+}
@@ -1,5 +1,6 @@
 use std::ops::{Add, BitXor, Div, Index, IndexMut, Mul, Sub};
 
+/// 2D vector; `Vec2f` in practice.
 #[derive(Debug, Clone, Copy)]
 pub struct Vec2D<T>
 where
@@ -9,6 +10,15 @@ where
     pub y: T,
 }
 
+impl<T> Default for Vec2D<T>
+where
+    T: num::Num + Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Vec2D<T>
 where
     T: num::Num + Copy,
@@ -61,7 +71,7 @@ where
     fn mul(self, _rhs: f32) -> Self::Output {
         Self {
             x: T::lossy_from_f32(self.x.to_f32().unwrap() * _rhs),
-            y: T::lossy_from_f32(self.x.to_f32().unwrap() * _rhs),
+            y: T::lossy_from_f32(self.y.to_f32().unwrap() * _rhs),
         }
     }
 }
@@ -90,6 +100,7 @@ impl<T: num::Num> IndexMut<usize> for Vec2D<T> {
     }
 }
 
+/// 3D vector. `*` between two vectors is the dot product and `^` the cross product.
 #[derive(Debug, Clone, Copy)]
 pub struct Vec3D<T>
 where
@@ -114,7 +125,7 @@ where
     pub fn from_slice(s: &[f32]) -> Vec3f {
         Vec3f::new_args(s[0], s[1], s[2])
     }
-    pub fn from_vec(v: &Vec<T>) -> Self {
+    pub fn from_vec(v: &[T]) -> Self {
         Self {
             x: v[0],
             y: v[1],
@@ -304,6 +315,7 @@ impl dt {
 
 const DEFAULT_ALLOC: usize = 4;
 
+/// Row-major matrix of up to 4x4 `f32`, indexed by row.
 #[derive(Clone, Debug)]
 pub struct Matrix {
     m: [[f32; 4]; 4],
@@ -332,15 +344,15 @@ impl Matrix {
     pub fn col(&self, idx: i32) -> [f32; 4] {
         assert!(idx >= 0 && idx < self.cols);
         let mut ret = [0f32; 4];
-        for i in 0..self.rows as usize {
-            ret[i] = self.m[i][idx as usize];
+        for (r, row) in ret.iter_mut().zip(&self.m[..self.rows as usize]) {
+            *r = row[idx as usize];
         }
         ret
     }
-    pub fn set_col(&mut self, idx: i32, v: &Vec<f32>) {
+    pub fn set_col(&mut self, idx: i32, v: &[f32]) {
         assert!(idx < self.cols);
-        for i in 0..self.rows as usize {
-            self.m[i][idx as usize] = v[i];
+        for (row, &x) in self.m[..self.rows as usize].iter_mut().zip(v) {
+            row[idx as usize] = x;
         }
     }
     pub fn transpose(&self) -> Self {
@@ -490,11 +502,9 @@ pub fn cross<T: num::Num + Copy>(v1: Vec3D<T>, v2: Vec3D<T>) -> Vec3D<T> {
     }
 }
 
+/// Extends `v` with a fourth component, 1 unless given.
 pub fn embed(v: &Vec3f, fill: Option<f32>) -> Vec4f {
-    let fill = match fill {
-        Some(val) => val,
-        None => 1.,
-    };
+    let fill = fill.unwrap_or(1.);
     let mut ret = Vec4f::new();
     for i in (0..4).rev() {
         ret[i] = if i < 3 { v[i] } else { fill };
@@ -502,11 +512,8 @@ pub fn embed(v: &Vec3f, fill: Option<f32>) -> Vec4f {
     ret
 }
 
-pub fn embed_refactor<const T: usize>(v: &Vec<f32>, fill: Option<f32>) -> Vec<f32> {
-    let fill = match fill {
-        Some(val) => val,
-        None => 1.,
-    };
+pub fn embed_refactor<const T: usize>(v: &[f32], fill: Option<f32>) -> Vec<f32> {
+    let fill = fill.unwrap_or(1.);
 
     let mut ret: Vec<f32> = vec![];
     for i in 0..T {
@@ -515,6 +522,7 @@ pub fn embed_refactor<const T: usize>(v: &Vec<f32>, fill: Option<f32>) -> Vec<f3
     ret
 }
 
+/// The first two components of `v`.
 pub fn proj(v: Vec4f) -> Vec2f {
     let mut ret = Vec2f::new();
     for i in (0..2).rev() {
@@ -524,13 +532,10 @@ pub fn proj(v: Vec4f) -> Vec2f {
 }
 
 pub fn proj_refactor<T: Copy>(v: Vec<T>, len: usize) -> Vec<T> {
-    let mut ret: Vec<T> = vec![];
-    for i in 0..len {
-        ret.push(v[i]);
-    }
-    ret
+    v[..len].to_vec()
 }
 
+/// Homogeneous coordinates or RGBA colors; `a` is the fourth component.
 #[derive(Debug, Clone, Copy)]
 pub struct Vec4D<T>
 where
@@ -544,6 +549,12 @@ where
 
 pub type Vec4f = Vec4D<f32>;
 
+impl Default for Vec4f {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Vec4f {
     pub fn new() -> Self {
         Self {
@@ -553,11 +564,19 @@ impl Vec4f {
             a: 0.,
         }
     }
+    pub fn new_args(newx: f32, newy: f32, newz: f32, newa: f32) -> Self {
+        Self {
+            x: newx,
+            y: newy,
+            z: newz,
+            a: newa,
+        }
+    }
 }
 
-impl<T: num::Num> Into<Vec<T>> for Vec4D<T> {
-    fn into(self) -> Vec<T> {
-        vec![self.x, self.y, self.z, self.a]
+impl<T: num::Num> From<Vec4D<T>> for Vec<T> {
+    fn from(v: Vec4D<T>) -> Self {
+        vec![v.x, v.y, v.z, v.a]
     }
 }
 
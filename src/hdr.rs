@@ -0,0 +1,308 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use crate::float_image::FloatImage;
+use crate::tga::{TGAFormat, TGAImage};
+
+/*
+    HDR resolve and Radiance output
+
+    Turns a FloatImage render target into a displayable
+    TGAImage through exposure, a tone mapper and optional sRGB
+    encoding, and saves the raw buffer as a Radiance RGBE
+    (.hdr) file with run-length encoded scanlines.
+*/
+
+/// Shortest run the Radiance encoder stores as a run packet instead of literals.
+const MIN_RUN: usize = 4;
+
+/// Curves mapping exposed linear intensities to [0, 1].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    /// Cut everything above 1.
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic reference curve.
+    AcesFilmic,
+    /// 1 - exp(-x), like film exposure.
+    Exposure,
+}
+
+impl ToneMapper {
+    pub fn apply(self, x: f32) -> f32 {
+        let x = x.max(0.);
+        match self {
+            ToneMapper::Clamp => x,
+            ToneMapper::Reinhard => x / (1. + x),
+            ToneMapper::AcesFilmic => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapper::Exposure => 1. - (-x).exp(),
+        }
+        .min(1.)
+    }
+}
+
+/// Encodes a linear intensity in [0, 1] with the sRGB transfer function.
+pub fn srgb_encode(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// Decodes an sRGB encoded value in [0, 1] to linear intensity.
+pub fn srgb_decode(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Shared-exponent encoding of one pixel, as in Greg Ward's `float2rgbe`.
+fn to_rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let v = rgb[0].max(rgb[1]).max(rgb[2]);
+    if v < 1e-32 || !v.is_finite() {
+        return [0; 4];
+    }
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f32.powi(e) >= 1. {
+        e += 1;
+    }
+    let scale = 256. / 2f32.powi(e);
+    [
+        (rgb[0].max(0.) * scale) as u8,
+        (rgb[1].max(0.) * scale) as u8,
+        (rgb[2].max(0.) * scale) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+/// Appends one channel of an RLE scanline: run packets `128 + n, value` and literal packets `n, values...`.
+fn write_rle_channel(out: &mut Vec<u8>, data: &[u8]) {
+    let mut i = 0;
+    while i < data.len() {
+        // look for the next run long enough to be worth a run packet
+        let mut j = i;
+        let mut run = 0;
+        while j < data.len() {
+            run = 1;
+            while j + run < data.len() && run < 127 && data[j + run] == data[j] {
+                run += 1;
+            }
+            if run >= MIN_RUN {
+                break;
+            }
+            j += run;
+        }
+        while i < j {
+            let n = (j - i).min(128);
+            out.push(n as u8);
+            out.extend_from_slice(&data[i..i + n]);
+            i += n;
+        }
+        if j < data.len() {
+            out.push((128 + run) as u8);
+            out.push(data[j]);
+            i = j + run;
+        }
+    }
+}
+
+impl FloatImage {
+    /// Applies `exposure`, the tone mapper and optionally sRGB encoding, producing an RGB
+    /// image (grayscale for one and two channel images). Alpha is dropped.
+    pub fn resolve(&self, tone_mapper: ToneMapper, exposure: f32, srgb: bool) -> TGAImage {
+        let channels = self.get_channels() as usize;
+        let bytespp = if channels >= 3 {
+            TGAFormat::RGB
+        } else {
+            TGAFormat::Grayscale
+        } as usize;
+        let mut img = TGAImage::new_dimensions(self.get_width(), self.get_height(), bytespp as i32);
+        let out = img.buffer_mut().unwrap();
+        for (px, src) in out.chunks_mut(bytespp).zip(self.buffer().chunks(channels)) {
+            for (c, &s) in src[..bytespp].iter().enumerate() {
+                let mut v = tone_mapper.apply(s * exposure);
+                if srgb {
+                    v = srgb_encode(v);
+                }
+                // TGAImage stores BGR
+                let to = if bytespp == 3 { 2 - c } else { c };
+                px[to] = (v * 255.).round() as u8;
+            }
+        }
+        img
+    }
+
+    /// Writes the raw buffer as a Radiance RGBE file. One channel images are saved as gray.
+    pub fn write_hdr_file(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_hdr_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn write_hdr_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (w, h) = (self.get_width() as usize, self.get_height() as usize);
+        write!(
+            out,
+            "#?RADIANCE\nSOFTWARE=tinyrender {}\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            env!("CARGO_PKG_VERSION"),
+            h,
+            w
+        )?;
+        let channels = self.get_channels() as usize;
+        let mut rgbe = vec![0u8; w * 4];
+        let mut line = Vec::with_capacity(w * 5);
+        for row in self.buffer().chunks(w * channels).take(h) {
+            for (dst, px) in rgbe.chunks_mut(4).zip(row.chunks(channels)) {
+                let rgb = if channels >= 3 {
+                    [px[0], px[1], px[2]]
+                } else {
+                    [px[0]; 3]
+                };
+                dst.copy_from_slice(&to_rgbe(rgb));
+            }
+            line.clear();
+            // the RLE scheme only exists for widths in 8..32768
+            if (8..32768).contains(&w) {
+                line.extend_from_slice(&[2, 2, (w >> 8) as u8, w as u8]);
+                let mut plane = vec![0u8; w];
+                for c in 0..4 {
+                    for x in 0..w {
+                        plane[x] = rgbe[x * 4 + c];
+                    }
+                    write_rle_channel(&mut line, &plane);
+                }
+            } else {
+                line.extend_from_slice(&rgbe);
+            }
+            out.write_all(&line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Decodes the pixels of an RLE or flat Radiance file written by `write_hdr_to`.
+    fn read_rgbe(bytes: &[u8], w: usize, h: usize) -> Vec<[u8; 4]> {
+        let mut pos = bytes.windows(2).position(|p| p == b"\n\n").unwrap() + 2;
+        pos += bytes[pos..].iter().position(|&b| b == b'\n').unwrap() + 1;
+        let mut pixels = Vec::new();
+        for _ in 0..h {
+            let mut row = vec![[0u8; 4]; w];
+            if bytes[pos] == 2 && bytes[pos + 1] == 2 {
+                assert_eq!(
+                    ((bytes[pos + 2] as usize) << 8) | bytes[pos + 3] as usize,
+                    w
+                );
+                pos += 4;
+                for c in 0..4 {
+                    let mut x = 0;
+                    while x < w {
+                        let n = bytes[pos] as usize;
+                        if n > 128 {
+                            for px in &mut row[x..x + n - 128] {
+                                px[c] = bytes[pos + 1];
+                            }
+                            x += n - 128;
+                            pos += 2;
+                        } else {
+                            for k in 0..n {
+                                row[x + k][c] = bytes[pos + 1 + k];
+                            }
+                            x += n;
+                            pos += 1 + n;
+                        }
+                    }
+                }
+            } else {
+                for px in row.iter_mut() {
+                    px.copy_from_slice(&bytes[pos..pos + 4]);
+                    pos += 4;
+                }
+            }
+            pixels.extend(row);
+        }
+        assert_eq!(pos, bytes.len());
+        pixels
+    }
+
+    #[test]
+    fn tone_mappers() {
+        for &t in &[
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::AcesFilmic,
+            ToneMapper::Exposure,
+        ] {
+            assert_eq!(t.apply(0.), 0.);
+            assert_eq!(t.apply(-1.), 0.);
+            assert!(t.apply(1e6) <= 1. && t.apply(1e6) > 0.99);
+            assert!(t.apply(0.5) < t.apply(0.6));
+        }
+        assert_eq!(ToneMapper::Reinhard.apply(1.), 0.5);
+        assert!((ToneMapper::AcesFilmic.apply(1.) - 0.8038).abs() < 1e-3);
+        assert!((srgb_encode(0.5) - 0.7354).abs() < 1e-3);
+        assert!((srgb_encode(1.) - 1.).abs() < 1e-6);
+        for &x in &[0., 0.002, 0.04, 0.3, 0.8, 1.] {
+            assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1e-5, "{}", x);
+        }
+    }
+
+    #[test]
+    fn resolve_to_tga() {
+        let hdr = FloatImage::from_data(2, 1, 4, vec![0.5, 4., 0., 1., 1., 1., 1., 1.]).unwrap();
+        let img = hdr.resolve(ToneMapper::Clamp, 1., false);
+        assert_eq!(img.get_bytespp(), TGAFormat::RGB as i32);
+        assert_eq!(img.buffer().unwrap(), [0, 255, 128, 255, 255, 255]);
+        let img = hdr.resolve(ToneMapper::Reinhard, 2., true);
+        // 0.5 * 2 -> 0.5 after Reinhard -> 188 in sRGB
+        assert_eq!(img.get(0, 0)[2], 188);
+    }
+
+    #[test]
+    fn rgbe_encoding() {
+        assert_eq!(to_rgbe([0., 0., 0.]), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe([1., 0.5, 0.25]), [128, 64, 32, 129]);
+        assert_eq!(to_rgbe([0.5, 0., 0.]), [128, 0, 0, 128]);
+        assert_eq!(to_rgbe([1000., 0., 0.]), [250, 0, 0, 138]);
+    }
+
+    #[test]
+    fn writes_radiance_files() {
+        for &w in &[5usize, 300] {
+            let h = 3;
+            let mut img = FloatImage::new_dimensions(w as i32, h as i32, 4);
+            for y in 0..h {
+                for x in 0..w {
+                    // long flat stretches and noisy ones, to get both packet kinds
+                    let v = if x < w / 2 {
+                        1.
+                    } else {
+                        (x * 7 % 13) as f32 / 4.
+                    };
+                    img.set(x as i32, y as i32, &[v, v / 2., y as f32, 1.]);
+                }
+            }
+            let mut bytes = Vec::new();
+            img.write_hdr_to(&mut bytes).unwrap();
+            assert!(bytes.starts_with(b"#?RADIANCE\n"));
+            let header = format!("\n-Y {} +X {}\n", h, w);
+            assert!(bytes.windows(header.len()).any(|p| p == header.as_bytes()));
+            if w > 8 {
+                assert!(bytes.len() < w * h * 4);
+            }
+
+            let pixels = read_rgbe(&bytes, w, h);
+            for (i, px) in img.buffer().chunks(4).enumerate() {
+                assert_eq!(pixels[i], to_rgbe([px[0], px[1], px[2]]));
+            }
+        }
+    }
+}
@@ -0,0 +1,1269 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+use crate::tga::{DecodeLimits, TGAFormat, TGAImage};
+
+/*
+    JPEG decoder for TGAImage
+
+    Reads 8-bit baseline, extended sequential and progressive
+    Huffman-coded JPEGs with one (grayscale) or three (YCbCr or
+    RGB) components. Coefficients of every scan are collected
+    per component first, then dequantized, run through the IDCT,
+    upsampled and color converted in one go.
+*/
+
+/// Zigzag scan position to natural (row-major) coefficient index.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const SOF0: u8 = 0xc0;
+const SOF1: u8 = 0xc1;
+const SOF2: u8 = 0xc2;
+const DHT: u8 = 0xc4;
+const RST0: u8 = 0xd0;
+const RST7: u8 = 0xd7;
+const SOI: u8 = 0xd8;
+const EOI: u8 = 0xd9;
+const SOS: u8 = 0xda;
+const DQT: u8 = 0xdb;
+const DRI: u8 = 0xdd;
+const APP14: u8 = 0xee;
+
+/// Reasons a JPEG file can fail to decode. Offsets are in bytes from the start of the file.
+#[derive(Debug)]
+pub enum JpegError {
+    Io(io::Error),
+    NotJpeg,
+    Truncated {
+        offset: u64,
+    },
+    /// Lossless, hierarchical and arithmetic-coded files are not supported.
+    Unsupported {
+        marker: u8,
+    },
+    /// Only 8-bit samples are supported.
+    UnsupportedPrecision {
+        marker: u8,
+        bits: u8,
+    },
+    /// Only grayscale and three component (YCbCr or RGB) images are supported, not CMYK.
+    UnsupportedComponents {
+        marker: u8,
+        count: u8,
+    },
+    BadDimensions {
+        width: u32,
+        height: u32,
+    },
+    /// The image exceeds the `DecodeLimits` it was decoded with.
+    TooLarge {
+        width: u32,
+        height: u32,
+    },
+    /// A marker segment has an invalid length or contents.
+    BadSegment {
+        marker: u8,
+        offset: u64,
+    },
+    /// A scan uses a Huffman or quantization table that was never defined.
+    MissingTable {
+        offset: u64,
+    },
+    BadHuffmanCode {
+        offset: u64,
+    },
+    /// The entropy-coded data doesn't fit the scan it belongs to.
+    BadScan {
+        offset: u64,
+    },
+}
+
+impl fmt::Display for JpegError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JpegError::Io(e) => write!(f, "{e}"),
+            JpegError::NotJpeg => write!(f, "not a JPEG file"),
+            JpegError::Truncated { offset } => write!(f, "data truncated at byte {offset}"),
+            JpegError::Unsupported { marker } => {
+                write!(f, "unsupported JPEG variant (marker 0x{marker:02x})")
+            }
+            JpegError::UnsupportedPrecision { marker, bits } => {
+                write!(f, "unsupported {bits}-bit samples (marker 0x{marker:02x})")
+            }
+            JpegError::UnsupportedComponents { marker, count } => {
+                write!(
+                    f,
+                    "unsupported {count} component image (marker 0x{marker:02x})"
+                )
+            }
+            JpegError::BadDimensions { width, height } => {
+                write!(f, "bad image dimensions {width}x{height}")
+            }
+            JpegError::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "image dimensions {width}x{height} exceed the decoding limits"
+                )
+            }
+            JpegError::BadSegment { marker, offset } => {
+                write!(f, "malformed segment 0x{marker:02x} at byte {offset}")
+            }
+            JpegError::MissingTable { offset } => {
+                write!(f, "scan at byte {offset} uses an undefined table")
+            }
+            JpegError::BadHuffmanCode { offset } => {
+                write!(f, "invalid Huffman code near byte {offset}")
+            }
+            JpegError::BadScan { offset } => write!(f, "corrupt scan data near byte {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for JpegError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JpegError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for JpegError {
+    fn from(e: io::Error) -> Self {
+        JpegError::Io(e)
+    }
+}
+
+/// Canonical Huffman table, decoded one bit at a time by code length.
+struct HuffmanTable {
+    /// Largest code of each length, or -1 when there is none.
+    maxcode: [i32; 17],
+    /// Index into `values` of the first code of each length, minus that code.
+    offset: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl HuffmanTable {
+    fn new(counts: &[u8], values: &[u8]) -> Self {
+        let mut maxcode = [-1; 17];
+        let mut offset = [0; 17];
+        let mut code = 0;
+        let mut k = 0;
+        for len in 1..=16 {
+            let n = counts[len - 1] as i32;
+            offset[len] = k - code;
+            if n > 0 {
+                code += n;
+                k += n;
+                maxcode[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable {
+            maxcode,
+            offset,
+            values: values.to_vec(),
+        }
+    }
+}
+
+/// Reads bits from an entropy-coded segment, removing stuffed zero bytes and stopping at markers.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    nbits: u32,
+    /// Set once a marker is reached; all further bits read as zero.
+    marker: Option<u8>,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        BitReader {
+            data,
+            pos,
+            acc: 0,
+            nbits: 0,
+            marker: None,
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), JpegError> {
+        while self.nbits <= 24 {
+            let mut byte = 0;
+            if self.marker.is_none() {
+                if self.pos >= self.data.len() {
+                    return Err(JpegError::Truncated {
+                        offset: self.pos as u64,
+                    });
+                }
+                byte = self.data[self.pos];
+                if byte == 0xff {
+                    match self.data.get(self.pos + 1) {
+                        Some(0) => self.pos += 2,
+                        Some(&m) => {
+                            self.marker = Some(m);
+                            byte = 0;
+                        }
+                        None => {
+                            return Err(JpegError::Truncated {
+                                offset: self.pos as u64,
+                            })
+                        }
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.acc |= (byte as u32) << (24 - self.nbits);
+            self.nbits += 8;
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u16, JpegError> {
+        if n == 0 {
+            return Ok(0);
+        }
+        if self.nbits < n {
+            self.fill()?;
+        }
+        let v = self.acc >> (32 - n);
+        self.acc <<= n;
+        self.nbits -= n;
+        Ok(v as u16)
+    }
+
+    fn bit(&mut self) -> Result<bool, JpegError> {
+        Ok(self.bits(1)? == 1)
+    }
+
+    /// Reads an `n`-bit magnitude and sign-extends it as in section F.2.2.1 of T.81.
+    fn receive_extend(&mut self, n: u8) -> Result<i32, JpegError> {
+        if n == 0 {
+            return Ok(0);
+        }
+        if n > 16 {
+            return Err(JpegError::BadScan {
+                offset: self.pos as u64,
+            });
+        }
+        let v = self.bits(n as u32)? as i32;
+        if v < 1 << (n - 1) {
+            Ok(v - (1 << n) + 1)
+        } else {
+            Ok(v)
+        }
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Result<u8, JpegError> {
+        let mut code = 0;
+        for len in 1..=16 {
+            code = (code << 1) | self.bits(1)? as i32;
+            if code <= table.maxcode[len] {
+                return table
+                    .values
+                    .get((table.offset[len] + code) as usize)
+                    .copied()
+                    .ok_or(JpegError::BadHuffmanCode {
+                        offset: self.pos as u64,
+                    });
+            }
+        }
+        Err(JpegError::BadHuffmanCode {
+            offset: self.pos as u64,
+        })
+    }
+
+    /// Drops the buffered bits and steps over the RSTn marker that must follow them.
+    fn restart(&mut self) -> Result<(), JpegError> {
+        if self.marker.is_none() {
+            // the encoder padded the last byte; the marker comes right after it
+            self.nbits = 0;
+            self.acc = 0;
+            self.fill()?;
+        }
+        match self.marker {
+            Some(m) if (RST0..=RST7).contains(&m) => {
+                self.pos += 2;
+                self.marker = None;
+                self.acc = 0;
+                self.nbits = 0;
+                Ok(())
+            }
+            _ => Err(JpegError::BadScan {
+                offset: self.pos as u64,
+            }),
+        }
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    tq: usize,
+    /// Blocks per line and block lines, padded to whole MCUs.
+    blocks_w: usize,
+    blocks_h: usize,
+    /// 64 coefficients per block in natural order, blocks stored row by row. Allocated by the
+    /// first scan holding the component.
+    coeffs: Vec<i32>,
+    dc_pred: i32,
+}
+
+struct Frame {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    hmax: usize,
+    vmax: usize,
+    mcus_x: usize,
+    mcus_y: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    /// Size in samples of component `c` before padding.
+    fn component_size(&self, c: &Component) -> (usize, usize) {
+        (
+            (self.width * c.h).div_ceil(self.hmax),
+            (self.height * c.v).div_ceil(self.vmax),
+        )
+    }
+}
+
+/// Parameters of one SOS segment.
+struct Scan {
+    /// Frame component index with its DC and AC table numbers.
+    components: Vec<(usize, usize, usize)>,
+    ss: usize,
+    se: usize,
+    ah: u8,
+    al: u8,
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    limits: DecodeLimits,
+    frame: Option<Frame>,
+    qtables: [Option<[u16; 64]>; 4],
+    dc_tables: [Option<HuffmanTable>; 4],
+    ac_tables: [Option<HuffmanTable>; 4],
+    restart_interval: usize,
+    /// Transform flag of an Adobe APP14 segment: 0 means the components are RGB.
+    adobe_transform: Option<u8>,
+    eobrun: u32,
+}
+
+impl<'a> Decoder<'a> {
+    fn u16_at(&self, pos: usize) -> Result<usize, JpegError> {
+        match self.data.get(pos..pos + 2) {
+            Some(b) => Ok(u16::from_be_bytes([b[0], b[1]]) as usize),
+            None => Err(JpegError::Truncated { offset: pos as u64 }),
+        }
+    }
+
+    fn decode(mut self) -> Result<TGAImage, JpegError> {
+        if self.data.len() < 2 || self.data[0] != 0xff || self.data[1] != SOI {
+            return Err(JpegError::NotJpeg);
+        }
+        self.pos = 2;
+        loop {
+            // skip fill bytes in front of the marker
+            while self.data.get(self.pos) == Some(&0xff)
+                && self.data.get(self.pos + 1) == Some(&0xff)
+            {
+                self.pos += 1;
+            }
+            let offset = self.pos as u64;
+            let marker = match self.data.get(self.pos..self.pos + 2) {
+                Some(&[0xff, m]) => m,
+                Some(_) => return Err(JpegError::BadSegment { marker: 0, offset }),
+                None => return Err(JpegError::Truncated { offset }),
+            };
+            if marker == EOI {
+                break;
+            }
+            if (RST0..=RST7).contains(&marker) {
+                self.pos += 2;
+                continue;
+            }
+            let len = self.u16_at(self.pos + 2)?;
+            let start = self.pos + 4;
+            if len < 2 || start + len - 2 > self.data.len() {
+                return Err(JpegError::Truncated { offset });
+            }
+            let segment = &self.data[start..start + len - 2];
+            self.pos = start + len - 2;
+            let bad = JpegError::BadSegment { marker, offset };
+            match marker {
+                SOF0 | SOF1 | SOF2 => self.read_frame(segment, marker, bad)?,
+                0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                    return Err(JpegError::Unsupported { marker })
+                }
+                DHT => self.read_huffman_tables(segment, bad)?,
+                DQT => self.read_quant_tables(segment, bad)?,
+                DRI => {
+                    if segment.len() != 2 {
+                        return Err(bad);
+                    }
+                    self.restart_interval = u16::from_be_bytes([segment[0], segment[1]]) as usize;
+                }
+                APP14 if segment.len() >= 12 && &segment[..5] == b"Adobe" => {
+                    self.adobe_transform = Some(segment[11]);
+                }
+                SOS => {
+                    let scan = self.read_scan_header(segment, bad)?;
+                    self.decode_scan(&scan, offset)?;
+                }
+                _ => {}
+            }
+        }
+        self.finish()
+    }
+
+    fn read_frame(&mut self, s: &[u8], marker: u8, bad: JpegError) -> Result<(), JpegError> {
+        if self.frame.is_some() || s.len() < 6 {
+            return Err(bad);
+        }
+        if s[0] != 8 {
+            return Err(JpegError::UnsupportedPrecision { marker, bits: s[0] });
+        }
+        let progressive = marker == SOF2;
+        let height = u16::from_be_bytes([s[1], s[2]]) as usize;
+        let width = u16::from_be_bytes([s[3], s[4]]) as usize;
+        if width == 0 || height == 0 {
+            return Err(JpegError::BadDimensions {
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+        if width > self.limits.max_width as usize || height > self.limits.max_height as usize {
+            return Err(JpegError::TooLarge {
+                width: width as u32,
+                height: height as u32,
+            });
+        }
+        let n = s[5] as usize;
+        if n != 1 && n != 3 {
+            return Err(JpegError::UnsupportedComponents {
+                marker,
+                count: s[5],
+            });
+        }
+        if s.len() != 6 + 3 * n {
+            return Err(bad);
+        }
+        let mut components = Vec::with_capacity(n);
+        for c in s[6..].chunks(3) {
+            let (h, v, tq) = ((c[1] >> 4) as usize, (c[1] & 15) as usize, c[2] as usize);
+            if !(1..=4).contains(&h) || !(1..=4).contains(&v) || tq > 3 {
+                return Err(bad);
+            }
+            components.push(Component {
+                id: c[0],
+                h,
+                v,
+                tq,
+                blocks_w: 0,
+                blocks_h: 0,
+                coeffs: Vec::new(),
+                dc_pred: 0,
+            });
+        }
+        let hmax = components.iter().map(|c| c.h).max().unwrap();
+        let vmax = components.iter().map(|c| c.v).max().unwrap();
+        let mcus_x = width.div_ceil(8 * hmax);
+        let mcus_y = height.div_ceil(8 * vmax);
+        for c in components.iter_mut() {
+            c.blocks_w = mcus_x * c.h;
+            c.blocks_h = mcus_y * c.v;
+        }
+        self.frame = Some(Frame {
+            width,
+            height,
+            progressive,
+            hmax,
+            vmax,
+            mcus_x,
+            mcus_y,
+            components,
+        });
+        Ok(())
+    }
+
+    fn read_huffman_tables(&mut self, mut s: &[u8], bad: JpegError) -> Result<(), JpegError> {
+        while !s.is_empty() {
+            if s.len() < 17 {
+                return Err(bad);
+            }
+            let (class, id) = (s[0] >> 4, (s[0] & 15) as usize);
+            let counts = &s[1..17];
+            let total: usize = counts.iter().map(|&n| n as usize).sum();
+            if class > 1 || id > 3 || total > 256 || s.len() < 17 + total {
+                return Err(bad);
+            }
+            let table = HuffmanTable::new(counts, &s[17..17 + total]);
+            if class == 0 {
+                self.dc_tables[id] = Some(table);
+            } else {
+                self.ac_tables[id] = Some(table);
+            }
+            s = &s[17 + total..];
+        }
+        Ok(())
+    }
+
+    fn read_quant_tables(&mut self, mut s: &[u8], bad: JpegError) -> Result<(), JpegError> {
+        while !s.is_empty() {
+            let (precision, id) = (s[0] >> 4, (s[0] & 15) as usize);
+            let size = if precision == 0 { 64 } else { 128 };
+            if precision > 1 || id > 3 || s.len() < 1 + size {
+                return Err(bad);
+            }
+            let mut table = [0u16; 64];
+            for k in 0..64 {
+                // tables are stored in zigzag order
+                table[ZIGZAG[k]] = if precision == 0 {
+                    s[1 + k] as u16
+                } else {
+                    u16::from_be_bytes([s[1 + 2 * k], s[2 + 2 * k]])
+                };
+            }
+            self.qtables[id] = Some(table);
+            s = &s[1 + size..];
+        }
+        Ok(())
+    }
+
+    fn read_scan_header(&self, s: &[u8], bad: JpegError) -> Result<Scan, JpegError> {
+        let frame = self.frame.as_ref().ok_or(JpegError::BadSegment {
+            marker: SOS,
+            offset: self.pos as u64,
+        })?;
+        let n = *s.first().ok_or(JpegError::Truncated {
+            offset: self.pos as u64,
+        })? as usize;
+        if n == 0 || n > frame.components.len() || s.len() != 4 + 2 * n {
+            return Err(bad);
+        }
+        let mut components = Vec::with_capacity(n);
+        for c in s[1..1 + 2 * n].chunks(2) {
+            let index = frame.components.iter().position(|fc| fc.id == c[0]).ok_or(
+                JpegError::BadSegment {
+                    marker: SOS,
+                    offset: self.pos as u64,
+                },
+            )?;
+            components.push((index, (c[1] >> 4) as usize & 3, (c[1] & 15) as usize & 3));
+        }
+        let tail = &s[1 + 2 * n..];
+        let scan = Scan {
+            components,
+            ss: tail[0] as usize,
+            se: tail[1] as usize,
+            ah: tail[2] >> 4,
+            al: tail[2] & 15,
+        };
+        let valid = if frame.progressive {
+            // DC and AC coefficients never share a progressive scan, AC scans hold one component
+            scan.ss <= scan.se
+                && scan.se < 64
+                && (scan.ss == 0) == (scan.se == 0)
+                && (scan.ss == 0 || n == 1)
+                && scan.al < 14
+        } else {
+            scan.ss == 0 && scan.se == 63 && scan.ah == 0 && scan.al == 0
+        };
+        if !valid {
+            return Err(bad);
+        }
+        Ok(scan)
+    }
+
+    fn decode_scan(&mut self, scan: &Scan, offset: u64) -> Result<(), JpegError> {
+        let missing = JpegError::MissingTable { offset };
+        let mut frame = self.frame.take().unwrap();
+        for &(c, td, ta) in &scan.components {
+            let needs_dc = scan.ss == 0 && scan.ah == 0;
+            let needs_ac = scan.se > 0;
+            if (needs_dc && self.dc_tables[td].is_none())
+                || (needs_ac && self.ac_tables[ta].is_none())
+                || self.qtables[frame.components[c].tq].is_none()
+            {
+                return Err(missing);
+            }
+            frame.components[c].dc_pred = 0;
+        }
+        self.eobrun = 0;
+
+        let single = scan.components.len() == 1;
+        let (units_x, units_y) = if single {
+            let c = &frame.components[scan.components[0].0];
+            let (w, h) = frame.component_size(c);
+            (w.div_ceil(8), h.div_ceil(8))
+        } else {
+            (frame.mcus_x, frame.mcus_y)
+        };
+        let total = units_x * units_y;
+
+        // The first scan of a component codes its DC coefficients, taking at least one bit per
+        // block, so refuse to allocate more blocks than the remaining input can possibly fill.
+        let mut new_blocks = 0;
+        for &(c, _, _) in &scan.components {
+            let comp = &frame.components[c];
+            if comp.coeffs.is_empty() {
+                if scan.ss > 0 {
+                    return Err(JpegError::BadSegment {
+                        marker: SOS,
+                        offset,
+                    });
+                }
+                new_blocks += if single {
+                    total
+                } else {
+                    total * comp.h * comp.v
+                };
+            }
+        }
+        if (self.data.len() - self.pos) * 8 < new_blocks {
+            return Err(JpegError::Truncated {
+                offset: self.data.len() as u64,
+            });
+        }
+        for &(c, _, _) in &scan.components {
+            let comp = &mut frame.components[c];
+            if comp.coeffs.is_empty() {
+                comp.coeffs = vec![0; comp.blocks_w * comp.blocks_h * 64];
+            }
+        }
+
+        let mut reader = BitReader::new(self.data, self.pos);
+        for unit in 0..total {
+            if self.restart_interval > 0 && unit > 0 && unit % self.restart_interval == 0 {
+                reader.restart()?;
+                for &(c, _, _) in &scan.components {
+                    frame.components[c].dc_pred = 0;
+                }
+                self.eobrun = 0;
+            }
+            let (ux, uy) = (unit % units_x, unit / units_x);
+            for &(c, td, ta) in &scan.components {
+                let comp = &mut frame.components[c];
+                let (bw, bh) = if single { (1, 1) } else { (comp.h, comp.v) };
+                for by in 0..bh {
+                    for bx in 0..bw {
+                        let block = (uy * bh + by) * comp.blocks_w + ux * bw + bx;
+                        let coeffs = &mut comp.coeffs[block * 64..block * 64 + 64];
+                        let dc = self.dc_tables[td].as_ref();
+                        let ac = self.ac_tables[ta].as_ref();
+                        if scan.ss == 0 {
+                            let diff = if scan.ah == 0 {
+                                let t = reader.decode(dc.unwrap())?;
+                                reader.receive_extend(t)?
+                            } else {
+                                0
+                            };
+                            if scan.ah == 0 {
+                                // valid 8-bit data stays well inside 16 bits; clamping keeps
+                                // hostile runs of differences from overflowing
+                                comp.dc_pred = (comp.dc_pred + diff).clamp(-32768, 32767);
+                                coeffs[0] = comp.dc_pred * (1 << scan.al);
+                            } else if reader.bit()? {
+                                coeffs[0] |= 1 << scan.al;
+                            }
+                        }
+                        if scan.se > 0 {
+                            let ac = ac.unwrap();
+                            let ss = scan.ss.max(1);
+                            let eobrun = &mut self.eobrun;
+                            if scan.ah == 0 {
+                                decode_ac_first(
+                                    &mut reader,
+                                    ac,
+                                    coeffs,
+                                    ss,
+                                    scan.se,
+                                    scan.al,
+                                    eobrun,
+                                )?;
+                            } else {
+                                decode_ac_refine(
+                                    &mut reader,
+                                    ac,
+                                    coeffs,
+                                    ss,
+                                    scan.se,
+                                    scan.al,
+                                    eobrun,
+                                )?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        // resume marker parsing where the entropy-coded segment ends
+        let mut pos = reader.pos;
+        while pos + 1 < self.data.len()
+            && !(self.data[pos] == 0xff
+                && self.data[pos + 1] != 0
+                && !(RST0..=RST7).contains(&self.data[pos + 1]))
+        {
+            pos += 1;
+        }
+        self.pos = pos;
+        self.frame = Some(frame);
+        Ok(())
+    }
+
+    /// Dequantizes, inverse transforms, upsamples and color converts the collected coefficients.
+    fn finish(self) -> Result<TGAImage, JpegError> {
+        let frame = self.frame.ok_or(JpegError::Truncated {
+            offset: self.pos as u64,
+        })?;
+        // a component no scan ever held has nothing to show
+        if frame.components.iter().any(|c| c.coeffs.is_empty()) {
+            return Err(JpegError::Truncated {
+                offset: self.pos as u64,
+            });
+        }
+        let idct = IdctTable::new();
+        let mut planes = Vec::with_capacity(frame.components.len());
+        for c in &frame.components {
+            let q = self.qtables[c.tq].ok_or(JpegError::MissingTable {
+                offset: self.pos as u64,
+            })?;
+            let stride = c.blocks_w * 8;
+            let mut plane = vec![0u8; stride * c.blocks_h * 8];
+            let mut block = [0f32; 64];
+            for b in 0..c.blocks_w * c.blocks_h {
+                let coeffs = &c.coeffs[b * 64..b * 64 + 64];
+                for k in 0..64 {
+                    // 16-bit tables can overflow an i32 product
+                    block[k] = (coeffs[k] as i64 * q[k] as i64) as f32;
+                }
+                idct.apply(&mut block);
+                let (bx, by) = (b % c.blocks_w, b / c.blocks_w);
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let v = (block[y * 8 + x] + 128.).round().clamp(0., 255.);
+                        plane[(by * 8 + y) * stride + bx * 8 + x] = v as u8;
+                    }
+                }
+            }
+            planes.push(plane);
+        }
+
+        let gray = frame.components.len() == 1;
+        let rgb = !gray
+            && (self.adobe_transform == Some(0)
+                || frame.components.iter().map(|c| c.id).eq(*b"RGB"));
+        let bytespp = if gray {
+            TGAFormat::Grayscale
+        } else {
+            TGAFormat::RGB
+        } as usize;
+        let mut img =
+            TGAImage::new_dimensions(frame.width as i32, frame.height as i32, bytespp as i32);
+        let out = img.buffer_mut().unwrap();
+        let mut samples = [0f32; 3];
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                for (i, c) in frame.components.iter().enumerate() {
+                    samples[i] = upsample(&frame, c, &planes[i], x, y);
+                }
+                let i = (x + y * frame.width) * bytespp;
+                if gray {
+                    out[i] = samples[0].round() as u8;
+                    continue;
+                }
+                let [r, g, b] = if rgb {
+                    samples
+                } else {
+                    let (luma, cb, cr) = (samples[0], samples[1] - 128., samples[2] - 128.);
+                    [
+                        luma + 1.402 * cr,
+                        luma - 0.344136 * cb - 0.714136 * cr,
+                        luma + 1.772 * cb,
+                    ]
+                };
+                out[i] = b.round().clamp(0., 255.) as u8;
+                out[i + 1] = g.round().clamp(0., 255.) as u8;
+                out[i + 2] = r.round().clamp(0., 255.) as u8;
+            }
+        }
+        Ok(img)
+    }
+}
+
+/// First pass over AC coefficients `ss..=se`; baseline scans are a single such pass.
+fn decode_ac_first(
+    reader: &mut BitReader,
+    table: &HuffmanTable,
+    coeffs: &mut [i32],
+    ss: usize,
+    se: usize,
+    al: u8,
+    eobrun: &mut u32,
+) -> Result<(), JpegError> {
+    if *eobrun > 0 {
+        *eobrun -= 1;
+        return Ok(());
+    }
+    let mut k = ss;
+    while k <= se {
+        let rs = reader.decode(table)?;
+        let (r, s) = ((rs >> 4) as usize, rs & 15);
+        if s == 0 {
+            if r < 15 {
+                *eobrun = (1 << r) - 1 + reader.bits(r as u32)? as u32;
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += r;
+        if k > se {
+            return Err(JpegError::BadScan {
+                offset: reader.pos as u64,
+            });
+        }
+        coeffs[ZIGZAG[k]] = reader.receive_extend(s)? * (1 << al);
+        k += 1;
+    }
+    Ok(())
+}
+
+/// Successive approximation pass over AC coefficients `ss..=se`, section G.1.2.3 of T.81.
+fn decode_ac_refine(
+    reader: &mut BitReader,
+    table: &HuffmanTable,
+    coeffs: &mut [i32],
+    ss: usize,
+    se: usize,
+    al: u8,
+    eobrun: &mut u32,
+) -> Result<(), JpegError> {
+    let p1 = 1 << al;
+    let m1 = -1 << al;
+    let refine = |reader: &mut BitReader, coef: &mut i32| -> Result<(), JpegError> {
+        if reader.bit()? && *coef & p1 == 0 {
+            *coef += if *coef >= 0 { p1 } else { m1 };
+        }
+        Ok(())
+    };
+    let mut k = ss;
+    if *eobrun == 0 {
+        while k <= se {
+            let rs = reader.decode(table)?;
+            let (mut r, s) = ((rs >> 4) as i32, rs & 15);
+            let mut value = 0;
+            if s == 0 {
+                if r < 15 {
+                    *eobrun = (1 << r) + reader.bits(r as u32)? as u32;
+                    break;
+                }
+            } else {
+                value = if reader.bit()? { p1 } else { m1 };
+            }
+            // skip r zero coefficients, refining the nonzero ones passed on the way
+            while k <= se {
+                let z = ZIGZAG[k];
+                if coeffs[z] != 0 {
+                    refine(reader, &mut coeffs[z])?;
+                } else {
+                    if r == 0 {
+                        break;
+                    }
+                    r -= 1;
+                }
+                k += 1;
+            }
+            if value != 0 {
+                if k > se {
+                    return Err(JpegError::BadScan {
+                        offset: reader.pos as u64,
+                    });
+                }
+                coeffs[ZIGZAG[k]] = value;
+            }
+            k += 1;
+        }
+    }
+    if *eobrun > 0 {
+        while k <= se {
+            let z = ZIGZAG[k];
+            if coeffs[z] != 0 {
+                refine(reader, &mut coeffs[z])?;
+            }
+            k += 1;
+        }
+        *eobrun -= 1;
+    }
+    Ok(())
+}
+
+/// Bilinearly interpolates component `c` at the center of image pixel (x, y).
+fn upsample(frame: &Frame, c: &Component, plane: &[u8], x: usize, y: usize) -> f32 {
+    let stride = c.blocks_w * 8;
+    if c.h == frame.hmax && c.v == frame.vmax {
+        return plane[y * stride + x] as f32;
+    }
+    let (w, h) = frame.component_size(c);
+    let sx = ((x as f32 + 0.5) * c.h as f32 / frame.hmax as f32 - 0.5).clamp(0., (w - 1) as f32);
+    let sy = ((y as f32 + 0.5) * c.v as f32 / frame.vmax as f32 - 0.5).clamp(0., (h - 1) as f32);
+    let (x0, y0) = (sx as usize, sy as usize);
+    let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+    let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+    let at = |x: usize, y: usize| plane[y * stride + x] as f32;
+    let top = at(x0, y0) + (at(x1, y0) - at(x0, y0)) * fx;
+    let bottom = at(x0, y1) + (at(x1, y1) - at(x0, y1)) * fx;
+    top + (bottom - top) * fy
+}
+
+/// Separable 8x8 inverse DCT with precomputed basis functions.
+struct IdctTable {
+    /// basis[x * 8 + u] = C(u) / 2 * cos((2x + 1) u pi / 16)
+    basis: [f32; 64],
+}
+
+impl IdctTable {
+    fn new() -> Self {
+        let mut basis = [0.; 64];
+        for x in 0..8 {
+            for u in 0..8 {
+                let cu = if u == 0 { 1. / 2f32.sqrt() } else { 1. };
+                basis[x * 8 + u] = cu / 2. * ((2 * x + 1) as f32 * u as f32 * PI / 16.).cos();
+            }
+        }
+        IdctTable { basis }
+    }
+
+    fn apply(&self, block: &mut [f32; 64]) {
+        let mut tmp = [0f32; 64];
+        // rows: frequency u along x
+        for v in 0..8 {
+            for x in 0..8 {
+                let mut sum = 0.;
+                for u in 0..8 {
+                    sum += self.basis[x * 8 + u] * block[v * 8 + u];
+                }
+                tmp[v * 8 + x] = sum;
+            }
+        }
+        // columns: frequency v along y
+        for y in 0..8 {
+            for x in 0..8 {
+                let mut sum = 0.;
+                for v in 0..8 {
+                    sum += self.basis[y * 8 + v] * tmp[v * 8 + x];
+                }
+                block[y * 8 + x] = sum;
+            }
+        }
+    }
+}
+
+impl TGAImage {
+    pub fn read_jpeg_file(&mut self, filename: &str) -> Result<(), JpegError> {
+        *self = TGAImage::from_jpeg_reader(File::open(filename)?)?;
+        Ok(())
+    }
+
+    pub fn from_jpeg_reader<R: Read>(reader: R) -> Result<TGAImage, JpegError> {
+        TGAImage::from_jpeg_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    pub fn from_jpeg_reader_with_limits<R: Read>(
+        mut reader: R,
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, JpegError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        TGAImage::from_jpeg_bytes_with_limits(&bytes, limits)
+    }
+
+    /// Decodes a grayscale or color JPEG into a grayscale or RGB image.
+    pub fn from_jpeg_bytes(bytes: &[u8]) -> Result<TGAImage, JpegError> {
+        TGAImage::from_jpeg_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    pub fn from_jpeg_bytes_with_limits(
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, JpegError> {
+        Decoder {
+            data: bytes,
+            pos: 0,
+            limits,
+            frame: None,
+            qtables: [None; 4],
+            dc_tables: [None, None, None, None],
+            ac_tables: [None, None, None, None],
+            restart_interval: 0,
+            adobe_transform: None,
+            eobrun: 0,
+        }
+        .decode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    /// Smooth RGB gradient that survives high quality compression nearly unchanged.
+    fn gradient(w: usize, h: usize) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(w * h * 3);
+        for y in 0..h {
+            for x in 0..w {
+                rgb.extend_from_slice(&[
+                    (x * 255 / w) as u8,
+                    (y * 255 / h) as u8,
+                    ((x + y) * 127 / (w + h)) as u8 + 64,
+                ]);
+            }
+        }
+        rgb
+    }
+
+    fn encode(
+        rgb: &[u8],
+        w: usize,
+        h: usize,
+        progressive: bool,
+        sampling: SamplingFactor,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = Encoder::new(&mut out, 100);
+        encoder.set_progressive(progressive);
+        encoder.set_sampling_factor(sampling);
+        encoder.set_restart_interval(5);
+        encoder
+            .encode(rgb, w as u16, h as u16, ColorType::Rgb)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn decodes_sss_texture() {
+        let img =
+            TGAImage::from_jpeg_bytes(include_bytes!("../obj/african_head/african_head_SSS.jpg"))
+                .unwrap();
+        assert_eq!((img.get_width(), img.get_height()), (512, 512));
+        assert_eq!(img.get_bytespp(), TGAFormat::RGB as i32);
+        let c = img.get(256, 256);
+        assert_eq!([c[2], c[1], c[0]], [89, 53, 53]);
+
+        // channel means of a reference decoder, in RGB order
+        let mut sum = [0u64; 3];
+        for px in img.buffer().unwrap().chunks(3) {
+            for i in 0..3 {
+                sum[i] += px[2 - i] as u64;
+            }
+        }
+        let mean = sum.map(|s| s as f64 / (512. * 512.));
+        for (m, expected) in mean.iter().zip(&[13.081, 7.743, 8.583]) {
+            assert!((m - expected).abs() < 0.05, "{:?}", mean);
+        }
+    }
+
+    #[test]
+    fn baseline_and_progressive_match_source() {
+        let (w, h) = (45, 29);
+        let rgb = gradient(w, h);
+        for &progressive in &[false, true] {
+            for &sampling in &[
+                SamplingFactor::R_4_4_4,
+                SamplingFactor::R_4_2_2,
+                SamplingFactor::R_4_2_0,
+            ] {
+                let img =
+                    TGAImage::from_jpeg_bytes(&encode(&rgb, w, h, progressive, sampling)).unwrap();
+                assert_eq!((img.get_width(), img.get_height()), (w as i32, h as i32));
+                let buf = img.buffer().unwrap();
+                for i in 0..w * h {
+                    for c in 0..3 {
+                        let d = buf[i * 3 + 2 - c] as i32 - rgb[i * 3 + c] as i32;
+                        assert!(
+                            d.abs() <= 6,
+                            "{} {:?} pixel {}: {}",
+                            progressive,
+                            sampling,
+                            i,
+                            d
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_grayscale() {
+        let (w, h) = (20, 11);
+        let luma: Vec<u8> = (0..w * h).map(|i| (i % w * 12) as u8).collect();
+        for &progressive in &[false, true] {
+            let mut out = Vec::new();
+            let mut encoder = Encoder::new(&mut out, 100);
+            encoder.set_progressive(progressive);
+            encoder
+                .encode(&luma, w as u16, h as u16, ColorType::Luma)
+                .unwrap();
+            let img = TGAImage::from_jpeg_bytes(&out).unwrap();
+            assert_eq!(img.get_bytespp(), TGAFormat::Grayscale as i32);
+            for (a, b) in img.buffer().unwrap().iter().zip(&luma) {
+                assert!((*a as i32 - *b as i32).abs() <= 2);
+            }
+        }
+    }
+
+    /// Grayscale JPEG whose every block has the largest DC difference the format can code,
+    /// dequantized by a 16-bit table of 65535s. Progressive files hold just the DC scan, with
+    /// successive approximation shift `al`.
+    fn max_dc_diffs(width: u16, height: u16, progressive: bool, al: u8) -> Vec<u8> {
+        let mut out = vec![0xff, 0xd8];
+        out.extend_from_slice(&[0xff, 0xdb, 0, 131, 0x10]);
+        out.extend_from_slice(&[0xff; 128]);
+        let sof = if progressive { SOF2 } else { SOF0 };
+        let [h0, h1] = height.to_be_bytes();
+        let [w0, w1] = width.to_be_bytes();
+        out.extend_from_slice(&[0xff, sof, 0, 11, 8, h0, h1, w0, w1, 1, 1, 0x11, 0]);
+        // a single 1-bit code in each table: 16-bit differences for DC, end of block for AC
+        for (class, symbol) in [(0x00, 16), (0x10, 0x00)] {
+            let mut dht = vec![0xff, 0xc4, 0, 20, class, 1];
+            dht.extend_from_slice(&[0; 15]);
+            dht.push(symbol);
+            out.extend_from_slice(&dht);
+        }
+        let (se, ahal) = if progressive { (0, al) } else { (63, 0) };
+        out.extend_from_slice(&[0xff, 0xda, 0, 8, 1, 1, 0x00, 0, se, ahal]);
+
+        let blocks = (width as usize).div_ceil(8) * (height as usize).div_ceil(8);
+        let mut bits = Vec::new();
+        for _ in 0..blocks {
+            bits.push(0);
+            bits.extend_from_slice(&[1; 16]);
+            if !progressive {
+                bits.push(0);
+            }
+        }
+        bits.resize(bits.len().div_ceil(8) * 8, 1);
+        for byte in bits.chunks(8) {
+            let b = byte.iter().fold(0, |acc, &bit| acc << 1 | bit);
+            out.push(b);
+            if b == 0xff {
+                out.push(0);
+            }
+        }
+        out.extend_from_slice(&[0xff, 0xd9]);
+        out
+    }
+
+    #[test]
+    fn survives_huge_coefficients() {
+        // 33024 blocks of +65535 add up past i32::MAX
+        let img = TGAImage::from_jpeg_bytes(&max_dc_diffs(2048, 1032, false, 0)).unwrap();
+        assert!(img.buffer().unwrap().iter().all(|&v| v == 255));
+
+        let img = TGAImage::from_jpeg_bytes(&max_dc_diffs(16, 24, true, 13)).unwrap();
+        assert_eq!((img.get_width(), img.get_height()), (16, 24));
+        assert!(img.buffer().unwrap().iter().all(|&v| v == 255));
+    }
+
+    #[test]
+    fn huge_frames_need_matching_input() {
+        // a 16384x16384 frame followed by a few bytes of scan data
+        let mut jpeg = max_dc_diffs(8, 8, false, 0);
+        let sof = jpeg
+            .windows(4)
+            .position(|w| w == [0xff, SOF0, 0, 11])
+            .unwrap();
+        jpeg[sof + 5..sof + 9].copy_from_slice(&[0x40, 0, 0x40, 0]);
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&jpeg),
+            Err(JpegError::Truncated { .. })
+        ));
+
+        // no scan at all
+        let frame_only = [
+            0xff, 0xd8, 0xff, 0xc0, 0, 17, 8, 0x40, 0, 0x40, 0, 3, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11,
+            0, 0xff, 0xd9,
+        ];
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&frame_only),
+            Err(JpegError::Truncated { .. })
+        ));
+
+        // a progressive AC scan before any DC scan of the component
+        let mut jpeg = max_dc_diffs(8, 8, true, 0);
+        let sos = jpeg.windows(2).position(|w| w == [0xff, SOS]).unwrap();
+        jpeg[sos + 7..sos + 9].copy_from_slice(&[1, 63]);
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&jpeg),
+            Err(JpegError::BadSegment { marker: SOS, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let jpeg = encode(&gradient(16, 16), 16, 16, false, SamplingFactor::R_4_2_0);
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&jpeg[1..]),
+            Err(JpegError::NotJpeg)
+        ));
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&jpeg[..jpeg.len() - 40]),
+            Err(JpegError::Truncated { .. })
+        ));
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&[0xff, 0xd8, 0xff, 0xc3, 0, 2]),
+            Err(JpegError::Unsupported { marker: 0xc3 })
+        ));
+        let sof = [0xff, 0xd8, 0xff, 0xc0, 0, 11, 8, 0, 0, 0, 4, 1, 1, 0x11, 0];
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&sof),
+            Err(JpegError::BadDimensions { .. })
+        ));
+        let jpeg = encode(&gradient(24, 16), 24, 16, false, SamplingFactor::R_4_4_4);
+        let limits = DecodeLimits {
+            max_width: 16,
+            max_height: 16,
+        };
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes_with_limits(&jpeg, limits),
+            Err(JpegError::TooLarge {
+                width: 24,
+                height: 16
+            })
+        ));
+        assert!(TGAImage::from_jpeg_reader_with_limits(&jpeg[..], DecodeLimits::default()).is_ok());
+        let twelve_bit = [0xff, 0xd8, 0xff, 0xc2, 0, 11, 12, 0, 4, 0, 4, 1, 1, 0x11, 0];
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&twelve_bit),
+            Err(JpegError::UnsupportedPrecision {
+                marker: 0xc2,
+                bits: 12
+            })
+        ));
+        let cmyk = [0xff, 0xd8, 0xff, 0xc1, 0, 8, 8, 0, 4, 0, 4, 4];
+        assert!(matches!(
+            TGAImage::from_jpeg_bytes(&cmyk),
+            Err(JpegError::UnsupportedComponents {
+                marker: 0xc1,
+                count: 4
+            })
+        ));
+    }
+}
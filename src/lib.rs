@@ -0,0 +1,39 @@
+//! tinyrender is a Rust port of the C++ [tinyrenderer](https://github.com/ssloy/tinyrenderer)
+//! software rasterizer.
+//!
+//! The pieces, from the bottom up:
+//!
+//! - [`geometry`]: small vectors and matrices.
+//! - [`tga`], [`png`], [`jpeg`], [`pnm`]: 8-bit images and their file formats.
+//! - [`float_image`], [`hdr`]: float render targets, tone mapping and Radiance output.
+//! - [`texture`], [`sampler`]: mipmapped textures and filtered lookups.
+//! - [`model`]: a Wavefront OBJ mesh with its diffuse, normal and specular maps.
+//! - [`our_gl`]: the rasterizer, with the [`our_gl::IShader`] trait for custom shaders.
+//! - [`shader`], [`render`]: the normal mapped Phong shader and a one-call renderer.
+//!
+//! Rendering a model and saving it as PNG:
+//!
+//! ```no_run
+//! use tinyrender::{hdr::ToneMapper, model::Model, render::*, texture::MipFilter};
+//!
+//! let model = Model::new_args("obj/african_head/african_head.obj", MipFilter::Box);
+//! let frame = render(&model, &RenderOptions::default());
+//! let image = frame.color.resolve(ToneMapper::Clamp, 1., true);
+//! image.write_png("african_head.png").unwrap();
+//! ```
+
+#![allow(non_snake_case, non_upper_case_globals, non_camel_case_types)]
+
+pub mod float_image;
+pub mod geometry;
+pub mod hdr;
+pub mod jpeg;
+pub mod model;
+pub mod our_gl;
+pub mod png;
+pub mod pnm;
+pub mod render;
+pub mod sampler;
+pub mod shader;
+pub mod texture;
+pub mod tga;
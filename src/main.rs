@@ -1,212 +1,468 @@
-mod geometry;
-mod model;
-mod our_gl;
-mod tga;
-
-use crate::{geometry::*, our_gl::*, tga::*};
-use model::*;
-use time::Instant;
-use clap::Parser;
-
-const WIDTH: i32 = 800;
-const HEIGHT: i32 = 800;
-
-const light_dir: Vec3f = Vec3f {
-    x: 1.,
-    y: 1.,
-    z: 1.,
-};
-const eye: Vec3f = Vec3f {
-    x: 1.,
-    y: 1.,
-    z: 3.,
-};
-const center: Vec3f = Vec3f {
-    x: 0.,
-    y: 0.,
-    z: 0.,
-};
-const up: Vec3f = Vec3f {
-    x: 0.,
-    y: 1.,
-    z: 0.,
+use clap::{ArgEnum, Parser};
+use std::{error::Error, io, process::ExitCode};
+use time::{Instant, OffsetDateTime, PrimitiveDateTime};
+use tinyrender::{
+    geometry::{cross, Vec3f},
+    hdr::ToneMapper,
+    model::Model,
+    our_gl::{Clipping, CullMode, FrontFace, RenderState},
+    render::{render, Camera, RenderOptions, MAX_IMAGE_SIZE},
+    sampler::{Filter, Sampler, Wrap, MAX_ANISOTROPY},
+    texture::{ColorSpace, MipFilter, Texture},
+    tga::TGAImage,
 };
 
-struct Shader<'a> {
-    model: &'a Model,
-    uniform_l: Vec3f,
-    varying_uv: Matrix,
-    varying_nrm: Matrix,
-    view_tri: Matrix,
-}
-
-impl<'a> Shader<'a> {
-    pub fn new(m: &'a Model, view_bundle: &ViewBundle) -> Self {
-        Self {
-            model: m,
-            uniform_l: *Vec3f::from_vec(&proj_refactor(
-                Into::<Vec<f32>>::into(view_bundle.ModelView.clone() * embed(&light_dir, Some(0.))),
-                3,
-            ))
-            .normalize(),
-            varying_uv: Matrix::new(Some(2), Some(3)),
-            varying_nrm: Matrix::new(Some(3), Some(3)),
-            view_tri: Matrix::new(Some(3), Some(3)),
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Tga,
+    TgaRle,
+    Png,
+    Ppm,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Tga | OutputFormat::TgaRle => "tga",
+            OutputFormat::Png => "png",
+            OutputFormat::Ppm => "ppm",
+        }
+    }
+
+    fn write(self, img: &TGAImage, path: &str) -> io::Result<()> {
+        match self {
+            OutputFormat::Tga => img.write_tga_file(path, false),
+            OutputFormat::TgaRle => img.write_tga_file(path, true),
+            OutputFormat::Png => img.write_png(path),
+            OutputFormat::Ppm => img.write_pnm_file(path, false),
         }
     }
 }
 
-impl<'a> IShader for Shader<'a> {
-    fn vertex(&mut self, iface: i32, nthvert: i32, view_bundle: &ViewBundle) -> Vec4f {
-        self.varying_uv.set_col(
-            nthvert,
-            &vec![
-                self.model.uv(iface, nthvert)[0],
-                self.model.uv(iface, nthvert)[1],
-            ],
-        );
-        self.varying_nrm.set_col(
-            nthvert,
-            &proj_refactor(
-                Into::<Vec<f32>>::into(
-                    (view_bundle.ModelView.clone()).invert_transpose()
-                        * embed(&self.model.norm(iface, nthvert), Some(0.)),
-                ),
-                3,
-            ),
-        );
-        let mut gl_Position =
-            view_bundle.ModelView.clone() * embed(&self.model.vert(iface, nthvert), None);
-        self.view_tri.set_col(
-            nthvert,
-            &proj_refactor(Into::<Vec<f32>>::into(gl_Position), 3),
-        );
-        gl_Position = view_bundle.Projection.clone() * gl_Position;
-        return gl_Position;
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum ClipPlanes {
+    Near,
+    Frustum,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Cull {
+    None,
+    Back,
+    Front,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Winding {
+    Ccw,
+    Cw,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum Tonemap {
+    Clamp,
+    Reinhard,
+    Aces,
+    Exposure,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+    Anisotropic,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum MipmapFilter {
+    Box,
+    Kaiser,
+}
+
+/// Parses an image width or height, from 1 to `MAX_IMAGE_SIZE`.
+fn parse_size(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(v) if (1..=MAX_IMAGE_SIZE).contains(&(v as i32)) => Ok(v),
+        _ => Err(format!(
+            "expected a size from 1 to {}, got {}",
+            MAX_IMAGE_SIZE, s
+        )),
     }
+}
 
-    fn fragment(&self, bar: Vec3f) -> (bool, TGAColor) {
-        let bn = (self.varying_nrm.clone() * [bar[0], bar[1], bar[2]])
-            .normalize()
-            .to_owned();
-        let uv = Vec2f::new_args(
-            (self.varying_uv.clone() * [bar[0], bar[1], bar[2]])[0],
-            (self.varying_uv.clone() * [bar[0], bar[1], bar[2]])[1],
-        );
-        // TODO: this matrix definition might be wrong
-        let mut AI = Matrix::new(Some(3), Some(3));
-        let col0 = self.view_tri.col(0);
-        let col1 = self.view_tri.col(1);
-        let col2 = self.view_tri.col(2);
-        AI[0] = [col1[0] - col0[0], col1[1] - col0[1], col1[2] - col0[2], 0.];
-        AI[1] = [col2[0] - col0[0], col2[1] - col0[1], col2[2] - col0[2], 0.];
-        AI[2] = [bn[0], bn[1], bn[2], 0.];
-        AI = AI.invert();
-
-        let mut i = AI.clone()
-            * [
-                self.varying_uv[0][1] - self.varying_uv[0][0],
-                self.varying_uv[0][2] - self.varying_uv[0][0],
-                0.,
-            ];
-        let mut j = AI.clone()
-            * [
-                self.varying_uv[1][1] - self.varying_uv[1][0],
-                self.varying_uv[1][2] - self.varying_uv[1][0],
-                0.,
-            ];
-        let mut B = Matrix::new(Some(3), Some(3));
-        B[0] = [
-            i.normalize().to_owned()[0],
-            i.normalize().to_owned()[1],
-            i.normalize().to_owned()[2],
-            0.,
-        ];
-        B[1] = [
-            j.normalize().to_owned()[0],
-            j.normalize().to_owned()[1],
-            j.normalize().to_owned()[2],
-            0.,
-        ];
-        B[2] = [bn[0], bn[1], bn[2], 0.];
-        B = B.transpose();
-
-        let n = (B * [
-            self.model.normal(&uv)[0],
-            self.model.normal(&uv)[1],
-            self.model.normal(&uv)[2],
-        ])
-        .normalize()
-        .to_owned();
-        let diff = 0f32.max(n * self.uniform_l);
-        let r = (n * (n * self.uniform_l) * 2. - self.uniform_l)
-            .normalize()
-            .to_owned();
-        let spec = (-r.z)
-            .max(0.)
-            .powf(5. + Shader::sample2D(self.model.specular(), &uv)[0] as f32);
-
-        let c = Shader::sample2D(self.model.diffuse(), &uv);
-        let mut gl_FragColor = TGAColor::new_rgba(255, 255, 255, 0);
-        for i in 0..3 {
-            gl_FragColor[i] = (10. + c[i] as f32 * (diff + spec).min(255.)) as u8;
-        }
-        (false, gl_FragColor)
+/// Parses a viewport margin, in [0, 0.5).
+fn parse_margin(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(v) if (0. ..0.5).contains(&v) => Ok(v),
+        _ => Err(format!("expected a margin in [0, 0.5), got {}", s)),
+    }
+}
+
+/// Parses `x,y,z`.
+fn parse_vec3(s: &str) -> Result<Vec3f, String> {
+    let v: Vec<f32> = s
+        .split(',')
+        .map(|c| c.trim().parse::<f32>())
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("{}: {}", s, e))?;
+    match v[..] {
+        [x, y, z] => Ok(Vec3f::new_args(x, y, z)),
+        _ => Err(format!("expected three comma separated numbers, got {}", s)),
     }
 }
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-
     /// Path to the obj file
     #[clap(short, long)]
     obj_path: String,
 
+    /// Where to write the rendered image [default: framebuffer.<format extension>]
+    #[clap(long)]
+    output: Option<String>,
+
+    /// Image format of the rendered framebuffer
+    #[clap(long, arg_enum, default_value = "tga")]
+    format: OutputFormat,
+
+    /// Width of the rendered image in pixels, at most 16384
+    #[clap(long, default_value = "800", parse(try_from_str = parse_size))]
+    width: u16,
+
+    /// Height of the rendered image in pixels, at most 16384
+    #[clap(long, default_value = "800", parse(try_from_str = parse_size))]
+    height: u16,
+
+    /// Fraction of the image size left empty around the viewport on each side
+    #[clap(long, default_value = "0.125", parse(try_from_str = parse_margin))]
+    viewport_margin: f32,
+
+    /// Camera position, as x,y,z [default: 1,1,3]
+    #[clap(long, parse(try_from_str = parse_vec3), allow_hyphen_values = true)]
+    eye: Option<Vec3f>,
+
+    /// Point the camera looks at, as x,y,z [default: 0,0,0]
+    #[clap(long, parse(try_from_str = parse_vec3), allow_hyphen_values = true)]
+    center: Option<Vec3f>,
+
+    /// Up direction of the camera, as x,y,z [default: 0,1,0]
+    #[clap(long, parse(try_from_str = parse_vec3), allow_hyphen_values = true)]
+    up: Option<Vec3f>,
+
+    /// Vertical field of view in degrees [default: about 33.6, the original projection's]
+    #[clap(long)]
+    fov: Option<f32>,
+
+    /// Distance from the eye of the near plane
+    #[clap(long)]
+    near: Option<f32>,
+
+    /// Distance from the eye of the far plane
+    #[clap(long)]
+    far: Option<f32>,
+
+    /// Move the camera along its viewing direction to fit the model's bounding box, and set
+    /// the near and far planes around it unless given
+    #[clap(long)]
+    auto_frame: bool,
+
+    /// Use a parallel projection, sized to show what the perspective one shows at the center
+    #[clap(long)]
+    orthographic: bool,
+
+    /// Clip triangles against the near plane only, or against all six frustum planes
+    #[clap(long, arg_enum, default_value = "near")]
+    clipping: ClipPlanes,
+
+    /// Skip triangles facing the camera (front), facing away (back) or none
+    #[clap(long, arg_enum, default_value = "back")]
+    cull: Cull,
+
+    /// Winding on screen of triangles facing the camera
+    #[clap(long, arg_enum, default_value = "ccw")]
+    front_face: Winding,
+
+    /// Filtering used for texture lookups
+    #[clap(long, arg_enum, default_value = "bilinear")]
+    texture_filter: TextureFilter,
+
+    /// Most samples per lookup for the anisotropic texture filter, from 1 to 16
+    #[clap(long, default_value = "16")]
+    max_anisotropy: f32,
+
+    /// Use this image as the diffuse map instead of the one next to the obj file
+    #[clap(long)]
+    diffuse_map: Option<String>,
+
+    /// Downsampling filter used to build texture mip levels
+    #[clap(long, arg_enum, default_value = "box")]
+    mip_filter: MipmapFilter,
+
+    /// Tone mapper used to resolve the HDR framebuffer
+    #[clap(long, arg_enum, default_value = "clamp")]
+    tonemap: Tonemap,
+
+    /// Exposure multiplier applied before tone mapping
+    #[clap(long, default_value = "1.0")]
+    exposure: f32,
+
+    /// Also write the unresolved HDR framebuffer to this path as a Radiance .hdr file
+    #[clap(long)]
+    hdr_output: Option<String>,
+
+    /// Also write the z-buffer to this path as a PFM file
+    #[clap(long)]
+    depth_output: Option<String>,
 }
 
+impl Args {
+    /// The `--output` path, or `framebuffer` with the extension of the output format.
+    fn output_path(&self) -> String {
+        match &self.output {
+            Some(path) => path.clone(),
+            None => format!("framebuffer.{}", self.format.extension()),
+        }
+    }
+}
 
-fn main() {
+fn main() -> ExitCode {
+    exit_code(run(&Args::parse()))
+}
 
-    let args = Args::parse();
-    let INPUT = &args.obj_path[..];
+/// Reports a failed run on stderr.
+fn exit_code(result: Result<(), Box<dyn Error>>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
-    let mut framebuffer = TGAImage::new_dimensions(WIDTH, HEIGHT, TGAFormat::RGB as i32);
-    let ModelView = lookat(eye, center, up);
-    let ViewPort = viewport(WIDTH / 8, HEIGHT / 8, WIDTH * 3 / 4, HEIGHT * 3 / 4);
-    let Projection = projection((eye - center).norm());
-    let view_bundle = ViewBundle {
-        ModelView: ModelView.clone(),
-        ViewPort: ViewPort.clone(),
-        Projection: Projection.clone(),
+fn run(args: &Args) -> Result<(), Box<dyn Error>> {
+    if !(1. ..=MAX_ANISOTROPY).contains(&args.max_anisotropy) {
+        return Err("the maximum anisotropy has to be between 1 and 16".into());
+    }
+    let input = &args.obj_path[..];
+
+    let mip_filter = match args.mip_filter {
+        MipmapFilter::Box => MipFilter::Box,
+        MipmapFilter::Kaiser => MipFilter::Kaiser,
     };
-    let mut zbuffer = vec![f32::MAX; (WIDTH * HEIGHT) as usize];
+    let mut model_wrapper = Model::new_args(input, mip_filter);
+    if let Some(path) = &args.diffuse_map {
+        let mut img = TGAImage::new();
+        Model::load_image(path, &mut img)
+            .map_err(|e| format!("texture file {} loading failed: {}", path, e))?;
+        model_wrapper.set_diffuse(Texture::with_mipmaps(img, mip_filter, ColorSpace::Srgb));
+    }
 
-    let model_wrapper = Model::new_args(INPUT);
+    let mut camera = Camera::default();
+    camera.eye = args.eye.unwrap_or(camera.eye);
+    camera.center = args.center.unwrap_or(camera.center);
+    camera.up = args.up.unwrap_or(camera.up);
+    camera.fov_y = args.fov.unwrap_or(camera.fov_y);
+    camera.orthographic = args.orthographic;
+    if (camera.eye - camera.center).norm() == 0. {
+        return Err("the eye and center have to be different points".into());
+    }
+    if cross(camera.up, camera.center - camera.eye).norm() == 0. {
+        return Err("the up direction can't be parallel to the viewing direction".into());
+    }
+    if !(camera.fov_y > 0. && camera.fov_y < 180.) {
+        return Err("the field of view has to be between 0 and 180 degrees".into());
+    }
+    if args.auto_frame {
+        let (min, max) = model_wrapper.bounding_box();
+        let viewport = RenderOptions {
+            width: args.width as i32,
+            height: args.height as i32,
+            viewport_margin: args.viewport_margin,
+            ..RenderOptions::default()
+        };
+        camera.frame_box(min, max, viewport.aspect());
+    }
+    camera.near = args.near.unwrap_or(camera.near);
+    camera.far = args.far.unwrap_or(camera.far);
+    if !(camera.near > 0. && camera.near < camera.far) {
+        return Err("the near plane has to be in front of the eye and before the far plane".into());
+    }
 
-    let mut shader = Shader::new(&model_wrapper, &view_bundle);
+    let sampler = match args.texture_filter {
+        TextureFilter::Nearest => Sampler::new(Filter::Nearest, Wrap::Repeat),
+        TextureFilter::Bilinear => Sampler::new(Filter::Bilinear, Wrap::Repeat),
+        TextureFilter::Trilinear => Sampler::trilinear(Wrap::Repeat),
+        TextureFilter::Anisotropic => Sampler::anisotropic(Wrap::Repeat, args.max_anisotropy),
+    };
+    let clipping = match args.clipping {
+        ClipPlanes::Near => Clipping::Near,
+        ClipPlanes::Frustum => Clipping::Frustum,
+    };
+    let options = RenderOptions {
+        width: args.width as i32,
+        height: args.height as i32,
+        viewport_margin: args.viewport_margin,
+        camera,
+        sampler,
+        state: RenderState {
+            clipping,
+            cull_mode: match args.cull {
+                Cull::None => CullMode::None,
+                Cull::Back => CullMode::Back,
+                Cull::Front => CullMode::Front,
+            },
+            front_face: match args.front_face {
+                Winding::Ccw => FrontFace::Ccw,
+                Winding::Cw => FrontFace::Cw,
+            },
+        },
+        ..RenderOptions::default()
+    };
     println!("Rendering {} triangles", model_wrapper.nfaces());
     let now = Instant::now();
-    for i in 0..model_wrapper.nfaces() {
-        let mut clip_vert = [Vec4f::new(); 3];
-        for j in 0..3 {
-            clip_vert[j as usize] = shader.vertex(i as i32, j, &view_bundle);
+    let frame = render(&model_wrapper, &options);
+    println!("Finished in {}", now.elapsed());
+    println!(
+        "Culled {} and clipped away {} triangles",
+        frame.stats.culled, frame.stats.clipped
+    );
+    let write_error = |path: &str, e: io::Error| format!("could not write {}: {}", path, e);
+    if let Some(path) = &args.hdr_output {
+        frame
+            .color
+            .write_hdr_file(path)
+            .map_err(|e| write_error(path, e))?;
+    }
+    let tone_mapper = match args.tonemap {
+        Tonemap::Clamp => ToneMapper::Clamp,
+        Tonemap::Reinhard => ToneMapper::Reinhard,
+        Tonemap::Aces => ToneMapper::AcesFilmic,
+        Tonemap::Exposure => ToneMapper::Exposure,
+    };
+    // shading is done in linear space, so the output is sRGB encoded after tone mapping
+    let mut framebuffer = frame.color.resolve(tone_mapper, args.exposure, true);
+    let postage_stamp = framebuffer.postage_stamp();
+    let metadata = framebuffer.metadata_mut();
+    metadata.software_id = format!("tinyrender {}", env!("CARGO_PKG_VERSION"));
+    metadata.job_name = String::from(input);
+    let render_time = OffsetDateTime::now_utc();
+    metadata.timestamp = Some(PrimitiveDateTime::new(
+        render_time.date(),
+        render_time.time(),
+    ));
+    metadata.postage_stamp = Some(Box::new(postage_stamp));
+    if let Some(path) = &args.depth_output {
+        frame
+            .depth
+            .write_pfm_file(path)
+            .map_err(|e| write_error(path, e))?;
+    }
+    let output = args.output_path();
+    args.format
+        .write(&framebuffer, &output)
+        .map_err(|e| write_error(&output, e))?;
+    println!("Wrote {}", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(extra: &[&str]) -> Result<Args, clap::Error> {
+        let args = ["tinyrender", "--obj-path", "obj/floor.obj"];
+        Args::try_parse_from(args.iter().chain(extra))
+    }
+
+    #[test]
+    fn output_formats() {
+        let mut img = TGAImage::new_dimensions(4, 2, 3);
+        img.set(1, 1, &tinyrender::tga::TGAColor::new_rgba(10, 20, 30, 255));
+        for &(format, ext) in &[
+            ("tga", "tga"),
+            ("tga-rle", "tga"),
+            ("png", "png"),
+            ("ppm", "ppm"),
+        ] {
+            let args = parse(&["--format", format]).unwrap();
+            assert_eq!(args.output_path(), format!("framebuffer.{}", ext));
+
+            let path = std::env::temp_dir().join(format!("tinyrender_cli_{}.{}", format, ext));
+            let path = path.to_str().unwrap();
+            args.format.write(&img, path).unwrap();
+            let bytes = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            let read = match args.format {
+                OutputFormat::Tga | OutputFormat::TgaRle => {
+                    // data type code 2 for raw true color, 10 for RLE
+                    let rle = matches!(args.format, OutputFormat::TgaRle);
+                    assert_eq!(bytes[2], if rle { 10 } else { 2 });
+                    TGAImage::from_bytes(&bytes).unwrap()
+                }
+                OutputFormat::Png => TGAImage::from_png_bytes(&bytes).unwrap(),
+                OutputFormat::Ppm => TGAImage::from_pnm_bytes(&bytes).unwrap(),
+            };
+            assert_eq!(read.buffer(), img.buffer(), "{}", format);
         }
-        //if i == 30 { panic!() }
-        triangle(
-            &clip_vert,
-            &shader,
-            &mut framebuffer,
-            &mut zbuffer,
-            &view_bundle,
-        );
+        let args = parse(&["--format", "png", "--output", "out.tga"]).unwrap();
+        assert_eq!(args.output_path(), "out.tga");
+        assert!(parse(&["--format", "bmp"]).is_err());
     }
-    println!("Finished in {}", now.elapsed());
-    framebuffer.flip_vertically();
-    framebuffer
-        .write_tga_file(
-            "/home/raunaks/Projects/tinyrender/obj/framebuffer.tga",
-            false,
-        )
+
+    #[test]
+    fn validates_size_and_margin() {
+        let args = parse(&[
+            "--width",
+            "16384",
+            "--height",
+            "1",
+            "--viewport-margin",
+            "0",
+        ])
         .unwrap();
+        assert_eq!(
+            (args.width, args.height, args.viewport_margin),
+            (16384, 1, 0.)
+        );
+        let args = parse(&[]).unwrap();
+        assert_eq!(
+            (args.width, args.height, args.viewport_margin),
+            (800, 800, 0.125)
+        );
+        for bad in &[
+            ["--width", "0"],
+            ["--height", "16385"],
+            ["--width", "65535"],
+            ["--height", "-3"],
+            ["--width", "wide"],
+            ["--viewport-margin", "0.5"],
+            ["--viewport-margin", "-0.1"],
+            ["--viewport-margin", "NaN"],
+        ] {
+            assert!(parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn write_failures_exit_with_failure() {
+        let args = parse(&[
+            "--width",
+            "8",
+            "--height",
+            "8",
+            "--output",
+            "/nonexistent/out.tga",
+        ]);
+        let result = run(&args.unwrap());
+        assert!(result
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("could not write"));
+        assert_eq!(exit_code(result), ExitCode::FAILURE);
+        assert_eq!(exit_code(Ok(())), ExitCode::SUCCESS);
+    }
 }
@@ -1,65 +1,128 @@
 use obj::Obj;
+use std::{error::Error, fs::File, path::Path};
 
 use crate::{
     geometry::{Vec2f, Vec3f},
-    tga::TGAImage,
+    sampler::Sampler,
+    texture::{ColorSpace, MipFilter, Texture},
+    tga::{DecodeLimits, TGAImage},
 };
 
 /*
     OBJ Model Wrapper Class
 
     Adds diffuse map capabilities to OBJ struct provided
-    by the obj crate.
+    by the obj crate. Maps are looked up next to the .obj
+    file as TGA, PNG or JPEG, and get mip levels built with
+    the requested filter. The diffuse map holds sRGB color;
+    normal and specular maps hold linear data.
 */
 
+/// A triangulated OBJ mesh with texture coordinates and normals, plus its texture maps.
 pub struct Model {
     obj: Obj,
-    diffusemap: TGAImage,
-    normalmap: TGAImage,
-    specularmap: TGAImage,
+    diffusemap: Texture,
+    normalmap: Texture,
+    specularmap: Texture,
 }
 
 impl Model {
-    pub fn new_args(filename: &str) -> Self {
+    /// Loads `filename` and the `_diffuse`, `_nm_tangent` and `_spec` maps next to it, building
+    /// their mip levels with `mip_filter`. Missing maps are reported and left empty.
+    pub fn new_args(filename: &str, mip_filter: MipFilter) -> Self {
         let mut diffusemap = TGAImage::new();
         let mut normalmap = TGAImage::new();
         let mut specularmap = TGAImage::new();
         let model = Obj::load(filename).unwrap();
-        Model::load_texture(&String::from(filename), "_diffuse.tga", &mut diffusemap);
-        Model::load_texture(&String::from(filename), "_nm_tangent.tga", &mut normalmap);
-        Model::load_texture(&String::from(filename), "_spec.tga", &mut specularmap);
+        Model::load_texture(filename, "_diffuse", &mut diffusemap);
+        Model::load_texture(filename, "_nm_tangent", &mut normalmap);
+        Model::load_texture(filename, "_spec", &mut specularmap);
         Self {
             obj: model,
-            diffusemap: diffusemap,
-            normalmap: normalmap,
-            specularmap: specularmap,
+            diffusemap: Texture::with_mipmaps(diffusemap, mip_filter, ColorSpace::Srgb),
+            normalmap: Texture::with_mipmaps(normalmap, mip_filter, ColorSpace::Linear),
+            specularmap: Texture::with_mipmaps(specularmap, mip_filter, ColorSpace::Linear),
         }
     }
 
-    pub fn load_texture(filename: &String, suffix: &str, img: &mut TGAImage) {
+    /// Loads `<obj name><suffix>` with the first of the .tga, .png and .jpg extensions that exists.
+    pub fn load_texture(filename: &str, suffix: &str, img: &mut TGAImage) {
         let dot = filename.rfind(".");
         if let Some(idx) = dot {
-            let mut texfile = String::from(&filename.clone()[0..idx]);
+            let mut texfile = String::from(&filename[0..idx]);
             texfile.push_str(suffix);
-            println!(
-                "Texture file {texfile} loading {}",
-                if let Ok(_) = img.read_tga_file(&texfile) {
-                    "ok"
-                } else {
-                    "failed"
+            for ext in &[".tga", ".png", ".jpg"] {
+                let path = format!("{texfile}{ext}");
+                if !Path::new(&path).exists() {
+                    continue;
                 }
-            );
+                match Model::load_image(&path, img) {
+                    Ok(()) => println!("Texture file {path} loading ok"),
+                    Err(e) => println!("Texture file {path} loading failed: {e}"),
+                }
+                return;
+            }
+            println!("Texture file {texfile}.tga/.png/.jpg not found");
             //img.flip_vertically();
         }
     }
 
+    /// Reads `path` into `img`, picking the decoder from its extension; TGA unless .png or .jpg.
+    pub fn load_image(path: &str, img: &mut TGAImage) -> Result<(), Box<dyn Error>> {
+        Model::load_image_with_limits(path, img, DecodeLimits::default())
+    }
+
+    /// Like `load_image`, refusing images larger than `limits` whatever their format.
+    pub fn load_image_with_limits(
+        path: &str,
+        img: &mut TGAImage,
+        limits: DecodeLimits,
+    ) -> Result<(), Box<dyn Error>> {
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let file = File::open(path)?;
+        *img = match &ext[..] {
+            "png" => TGAImage::from_png_reader_with_limits(file, limits)?,
+            "jpg" | "jpeg" => TGAImage::from_jpeg_reader_with_limits(file, limits)?,
+            _ => TGAImage::from_reader_with_limits(file, limits)?,
+        };
+        Ok(())
+    }
+
+    /// Replaces the diffuse map, e.g. with a test pattern.
+    pub fn set_diffuse(&mut self, diffusemap: Texture) {
+        self.diffusemap = diffusemap;
+    }
+
+    /// Number of triangles.
     pub fn nfaces(&self) -> usize {
         self.obj.data.objects[0].groups[0].polys.len()
     }
+    /// Smallest and largest coordinates of the vertices used by the faces.
+    pub fn bounding_box(&self) -> (Vec3f, Vec3f) {
+        let mut min = Vec3f::new_args(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Vec3f::new_args(f32::MIN, f32::MIN, f32::MIN);
+        for i in 0..self.nfaces() {
+            for j in 0..3 {
+                let v = self.vert(i as i32, j);
+                for k in 0..3 {
+                    min[k] = min[k].min(v[k]);
+                    max[k] = max[k].max(v[k]);
+                }
+            }
+        }
+        (min, max)
+    }
+
+    /// Position of corner `nthvert` of face `iface`.
     pub fn vert(&self, iface: i32, nthvert: i32) -> Vec3f {
         let idx = self.obj.data.objects[0].groups[0].polys[iface as usize].0[nthvert as usize].0;
         Vec3f::from_slice(&self.obj.data.position[idx])
     }
+    /// Texture coordinates of corner `nthvert` of face `iface`, with v pointing down.
     pub fn uv(&self, iface: i32, nthvert: i32) -> Vec2f {
         let idx = self.obj.data.objects[0].groups[0].polys[iface as usize].0[nthvert as usize]
             .1
@@ -70,6 +133,7 @@ impl Model {
             1. - self.obj.data.texture[idx][1],
         )
     }
+    /// Unit vertex normal of corner `nvert` of face `iface`.
     pub fn norm(&self, iface: i32, nvert: i32) -> Vec3f {
         let idx = self.obj.data.objects[0].groups[0].polys[iface as usize].0[nvert as usize]
             .2
@@ -79,19 +143,38 @@ impl Model {
             .normalize()
             .to_owned()
     }
-    pub fn normal(&self, uvf: &Vec2f) -> Vec3f {
-        let c = self.normalmap.get(
-            (uvf[0] * self.normalmap.get_width() as f32) as i32,
-            (uvf[1] * self.normalmap.get_height() as f32) as i32,
-        );
-        Vec3f::new_args(c[2] as f32, c[1] as f32, c[0] as f32) * 2. / 255.
-            - Vec3f::new_args(1., 1., 1.)
+    /// Tangent space normal from the normal map at `uvf`, components in [-1, 1].
+    pub fn normal(&self, sampler: &Sampler, uvf: &Vec2f, duv_dx: &Vec2f, duv_dy: &Vec2f) -> Vec3f {
+        let c = sampler.sample_grad(&self.normalmap, uvf, duv_dx, duv_dy);
+        Vec3f::new_args(c.x, c.y, c.z) * 2. - Vec3f::new_args(1., 1., 1.)
     }
 
-    pub fn diffuse(&self) -> &TGAImage {
+    pub fn diffuse(&self) -> &Texture {
         &self.diffusemap
     }
-    pub fn specular(&self) -> &TGAImage {
+    pub fn specular(&self) -> &Texture {
         &self.specularmap
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_image_applies_limits_to_every_format() {
+        let limits = DecodeLimits {
+            max_width: 256,
+            max_height: 256,
+        };
+        for path in &[
+            "obj/african_head/african_head_spec.tga",
+            "obj/african_head/african_head_SSS.jpg",
+        ] {
+            let mut img = TGAImage::new();
+            assert!(Model::load_image_with_limits(path, &mut img, limits).is_err());
+            Model::load_image(path, &mut img).unwrap();
+            assert!(img.get_width() > 256);
+        }
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{geometry::*, tga::*};
+use crate::{float_image::FloatImage, geometry::*, sampler::Sampler, texture::Texture, tga::*};
 
+/// Maps normalized device coordinates [-1, 1] to the screen rectangle at (x, y) of size w x h.
 pub fn viewport(x: i32, y: i32, w: i32, h: i32) -> Matrix {
     let mut m = Matrix::new(Some(4), Some(4));
     m[0] = [w as f32 / 2., 0., 0., x as f32 + w as f32 / 2.];
@@ -9,17 +10,39 @@ pub fn viewport(x: i32, y: i32, w: i32, h: i32) -> Matrix {
     m
 }
 
-pub fn projection(f: f32) -> Matrix {
+/*
+    Clip space follows OpenGL for x, y and w: the camera
+    looks down -z in view space and w is the distance in
+    front of it. Depth goes from 0 at the near plane to 1 at
+    the far plane, as in Direct3D and Vulkan, and the
+    z-buffer stores it after the perspective divide.
+*/
+
+/// Perspective projection with a vertical field of view of `fov_y` radians, `aspect` the width
+/// over the height of the viewport, and the near and far planes at those distances.
+pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Matrix {
+    let f = 1. / (fov_y / 2.).tan();
+    let mut Projection = Matrix::new(Some(4), Some(4));
+    Projection[0] = [f / aspect, 0., 0., 0.];
+    Projection[1] = [0., f, 0., 0.];
+    Projection[2] = [0., 0., far / (near - far), near * far / (near - far)];
+    Projection[3] = [0., 0., -1., 0.];
+    Projection
+}
+
+/// Parallel projection of the view space box from (l, b, -n) to (r, t, -f).
+pub fn orthographic(l: f32, r: f32, b: f32, t: f32, n: f32, f: f32) -> Matrix {
     let mut Projection = Matrix::new(Some(4), Some(4));
-    Projection[0] = [1., 0., 0., 0.];
-    Projection[1] = [0., -1., 0., 0.];
-    Projection[2] = [0., 0., 1., 0.];
-    Projection[3] = [0., 0., -1. / f, 0.];
+    Projection[0] = [2. / (r - l), 0., 0., -(r + l) / (r - l)];
+    Projection[1] = [0., 2. / (t - b), 0., -(t + b) / (t - b)];
+    Projection[2] = [0., 0., 1. / (n - f), n / (n - f)];
+    Projection[3] = [0., 0., 0., 1.];
     Projection
 }
 
+/// Model-view matrix of a camera at `eye` looking at `center`, with `up` pointing up.
 pub fn lookat(eye: Vec3f, center: Vec3f, up: Vec3f) -> Matrix {
-    let z = (center - eye).normalize().to_owned();
+    let z = (eye - center).normalize().to_owned();
     let x = cross(up, z).normalize().to_owned();
     let y = cross(z, x).normalize().to_owned();
     let mut Minv = Matrix::new(Some(4), Some(4));
@@ -35,39 +58,290 @@ pub fn lookat(eye: Vec3f, center: Vec3f, up: Vec3f) -> Matrix {
     Minv * Tr
 }
 
-pub fn barycentric(tri: &[Vec2f; 3], P: &Vec2f) -> Vec3f {
-    let mut ABC = Matrix::new(Some(3), Some(3));
-    ABC[0] = [tri[0][0], tri[0][1], 1., 0.];
-    ABC[1] = [tri[1][0], tri[1][1], 1., 0.];
-    ABC[2] = [tri[2][0], tri[2][1], 1., 0.];
-    if ABC.det() < 1e-3 {
-        return Vec3f::new_args(-1., 1., 1.);
-    }
-    ABC.invert_transpose() * [P[0], P[1], 1.]
-}
-
+/// The transforms of a draw call, the `ModelView`, `Viewport` and `Projection` globals of the
+/// C++ version.
 pub struct ViewBundle {
     pub ModelView: Matrix,
     pub ViewPort: Matrix,
     pub Projection: Matrix,
 }
 
+/// Anything `triangle` can shade into. Colors are linear RGBA with 1.0 as full intensity.
+pub trait RenderTarget {
+    fn dimensions(&self) -> (i32, i32);
+    fn write_fragment(&mut self, x: i32, y: i32, color: &Vec4f);
+}
+
+impl RenderTarget for TGAImage {
+    fn dimensions(&self) -> (i32, i32) {
+        (self.get_width(), self.get_height())
+    }
+
+    /// Clamps to [0, 1] and quantizes to 8 bits.
+    fn write_fragment(&mut self, x: i32, y: i32, color: &Vec4f) {
+        let q = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+        self.set(
+            x,
+            y,
+            &TGAColor::new_rgba(q(color.x), q(color.y), q(color.z), q(color.a)),
+        );
+    }
+}
+
+impl RenderTarget for FloatImage {
+    fn dimensions(&self) -> (i32, i32) {
+        (self.get_width(), self.get_height())
+    }
+
+    /// Stores the color unclamped, keeping as many of the RGBA channels as the image has.
+    fn write_fragment(&mut self, x: i32, y: i32, color: &Vec4f) {
+        let rgba = [color.x, color.y, color.z, color.a];
+        self.set(x, y, &rgba[..self.get_channels().min(4) as usize]);
+    }
+}
+
+/// Screen-space derivatives of the perspective-correct barycentric coordinates of a fragment,
+/// taken across the 2x2 pixel quad it is shaded in, like `dFdxFine`/`dFdyFine` on GPUs: along
+/// the fragment's own row and column of the quad.
+#[derive(Clone, Copy, Debug)]
+pub struct QuadDerivatives {
+    pub dx: Vec3f,
+    pub dy: Vec3f,
+}
+
+/// A vertex and fragment shader pair. `vertex` is called for the three corners of a face and
+/// returns clip coordinates; `triangle` then calls `fragment` for every covered pixel.
 pub trait IShader {
-    fn sample2D(img: &TGAImage, uvf: &Vec2f) -> TGAColor {
-        img.get(
-            (uvf[0] * img.get_width() as f32) as i32,
-            (uvf[1] * img.get_height() as f32) as i32,
-        )
+    /// RGBA color of `img` at `uvf`, in [0, 1].
+    fn sample2D(sampler: &Sampler, img: &TGAImage, uvf: &Vec2f) -> Vec4f {
+        sampler.sample(img, uvf)
+    }
+    /// RGBA color of `tex` at `uvf`, at the mip level picked from the UV derivatives.
+    fn sample2D_grad(
+        sampler: &Sampler,
+        tex: &Texture,
+        uvf: &Vec2f,
+        duv_dx: &Vec2f,
+        duv_dy: &Vec2f,
+    ) -> Vec4f {
+        sampler.sample_grad(tex, uvf, duv_dx, duv_dy)
     }
     fn vertex(&mut self, iface: i32, nthvert: i32, view_bundle: &ViewBundle) -> Vec4f;
-    fn fragment(&self, bar: Vec3f) -> (bool, TGAColor);
+    /// Returns whether to discard the fragment, and its RGBA color. `deriv` holds the screen-space
+    /// derivatives of `bar`, for varyings whose rate of change matters, like texture coordinates.
+    fn fragment(&self, bar: Vec3f, deriv: &QuadDerivatives) -> (bool, Vec4f);
+}
+
+/// Which clip space planes `triangle` clips against.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clipping {
+    /// Only the near plane, which is enough to keep w positive. The far plane is left to the depth
+    /// test and the sides to the screen bounds.
+    Near,
+    /// All six planes of the view frustum, so nothing is drawn outside the viewport.
+    Frustum,
+}
+
+/// Which triangles `triangle` skips by the way they face the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+
+/// The winding, as seen on screen, of triangles facing the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrontFace {
+    Ccw,
+    Cw,
+}
+
+/// Fixed-function state used by `triangle`.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderState {
+    pub clipping: Clipping,
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        RenderState {
+            clipping: Clipping::Near,
+            cull_mode: CullMode::Back,
+            front_face: FrontFace::Ccw,
+        }
+    }
 }
 
+/// What became of a triangle passed to `triangle`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriangleOutcome {
+    Drawn,
+    /// Entirely outside the clipping planes.
+    Clipped,
+    /// Facing the culled way, or covering no area on screen once snapped to the subpixel grid.
+    Culled,
+}
+
+/// Signed distances of a clip space point to the near, far, left, right, bottom and top planes,
+/// positive inside.
+fn plane_distances(v: &Vec4f) -> [f32; 6] {
+    [
+        v[2],
+        v[3] - v[2],
+        v[3] + v[0],
+        v[3] - v[0],
+        v[3] + v[1],
+        v[3] - v[1],
+    ]
+}
+
+/// Sutherland-Hodgman clipping of a triangle in homogeneous clip space. Returns the clipped
+/// convex polygon, each vertex with its barycentric coordinates in the original triangle, which
+/// stand in for the varyings: clip coordinates and varyings are both linear in them.
+pub fn clip_triangle(clip_verts: &[Vec4f], clipping: Clipping) -> Vec<(Vec4f, Vec3f)> {
+    let planes = match clipping {
+        Clipping::Near => 1,
+        Clipping::Frustum => 6,
+    };
+    let poly: Vec<(Vec4f, Vec3f)> = (0..3)
+        .map(|i| {
+            let mut bar = Vec3f::new_args(0., 0., 0.);
+            bar[i] = 1.;
+            (clip_verts[i], bar)
+        })
+        .collect();
+    (0..planes).fold(poly, |poly, p| {
+        clip_polygon(&poly, |v| plane_distances(v)[p])
+    })
+}
+
+/// One Sutherland-Hodgman step: the part of a convex polygon where `distance`, a linear function
+/// of clip coordinates, is not negative.
+fn clip_polygon(poly: &[(Vec4f, Vec3f)], distance: impl Fn(&Vec4f) -> f32) -> Vec<(Vec4f, Vec3f)> {
+    let mut clipped = Vec::with_capacity(poly.len() + 1);
+    for i in 0..poly.len() {
+        let (a, b) = (poly[i], poly[(i + 1) % poly.len()]);
+        let (da, db) = (distance(&a.0), distance(&b.0));
+        if da >= 0. {
+            clipped.push(a);
+        }
+        if (da >= 0.) != (db >= 0.) {
+            // where the edge crosses the plane
+            let t = da / (da - db);
+            let v = Vec4f::new_args(
+                a.0.x + (b.0.x - a.0.x) * t,
+                a.0.y + (b.0.y - a.0.y) * t,
+                a.0.z + (b.0.z - a.0.z) * t,
+                a.0.a + (b.0.a - a.0.a) * t,
+            );
+            clipped.push((v, a.1 + (b.1 - a.1) * t));
+        }
+    }
+    clipped
+}
+
+/// Twice the signed area on screen of a polygon given in clip coordinates in front of the eye,
+/// with its corners snapped as `rasterize` snaps them, positive if it winds counter-clockwise.
+fn screen_area(poly: &[(Vec4f, Vec3f)], Viewport: &Matrix) -> i64 {
+    let pts: Vec<[i64; 2]> = poly.iter().map(|(v, _)| snap_clip(v, Viewport)).collect();
+    (0..pts.len())
+        .map(|i| {
+            let (a, b) = (pts[i], pts[(i + 1) % pts.len()]);
+            a[0] * b[1] - b[0] * a[1]
+        })
+        .sum()
+}
+
+/// Draws one triangle given in clip coordinates, clipped and culled as `state` says, depth
+/// testing against `zbuffer`: fragments pass if their normalized depth is smaller than the stored
+/// one, so a z-buffer cleared to 1 also drops fragments beyond the far plane.
 pub fn triangle(
     clip_verts: &[Vec4f],
     shader: &impl IShader,
-    image: &mut TGAImage,
-    zbuffer: &mut Vec<f32>,
+    image: &mut impl RenderTarget,
+    zbuffer: &mut [f32],
+    view_bundle: &ViewBundle,
+    state: &RenderState,
+) -> TriangleOutcome {
+    let mut poly = clip_triangle(clip_verts, state.clipping);
+    // the guard band: far enough out to never show, even without frustum clipping
+    let vp = &view_bundle.ViewPort;
+    for axis in 0..2 {
+        let (scale, offset) = (vp[axis][axis], vp[axis][3]);
+        poly = clip_polygon(&poly, |v| scale * v[axis] + (offset + GUARD_BAND) * v[3]);
+        poly = clip_polygon(&poly, |v| (GUARD_BAND - offset) * v[3] - scale * v[axis]);
+    }
+    if poly.len() < 3 {
+        return TriangleOutcome::Clipped;
+    }
+    // clipping keeps the winding, and every corner left has a positive w
+    let area = screen_area(&poly, &view_bundle.ViewPort);
+    let ccw = area > 0;
+    let front = ccw == (state.front_face == FrontFace::Ccw);
+    let culled = match state.cull_mode {
+        CullMode::None => false,
+        CullMode::Back => !front,
+        CullMode::Front => front,
+    };
+    if culled || area == 0 {
+        return TriangleOutcome::Culled;
+    }
+    // the clipped polygon is convex, so a fan covers it
+    for i in 1..poly.len().saturating_sub(1) {
+        let verts = [poly[0].0, poly[i].0, poly[i + 1].0];
+        let bars = [poly[0].1, poly[i].1, poly[i + 1].1];
+        rasterize(&verts, &bars, shader, image, zbuffer, view_bundle);
+    }
+    TriangleOutcome::Drawn
+}
+
+/// Bits of subpixel precision screen positions are snapped to.
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL: i64 = 1 << SUBPIXEL_BITS;
+
+/// Triangles are clipped to this many pixels around the screen origin, which keeps snapped
+/// positions below 2^28 and edge functions, their products, well within an i64.
+const GUARD_BAND: f32 = (1 << 20) as f32;
+
+/// Fixed point screen position, with `SUBPIXEL_BITS` fractional bits.
+fn snap(p: Vec2f) -> [i64; 2] {
+    [
+        (p.x * SUBPIXEL as f32).round() as i64,
+        (p.y * SUBPIXEL as f32).round() as i64,
+    ]
+}
+
+/// Fixed point screen position of a point given in clip coordinates in front of the eye.
+fn snap_clip(v: &Vec4f, Viewport: &Matrix) -> [i64; 2] {
+    let p = Viewport.clone() * *v;
+    snap(proj(p / p[3]))
+}
+
+/// Twice the signed area of the triangle (a, b, p), positive if p is left of the line from a to
+/// b. Exact, as the positions are integers.
+fn edge_function(a: [i64; 2], b: [i64; 2], p: [i64; 2]) -> i64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Rasterizes a triangle lying in front of the near plane, whose corners sit at barycentric
+/// coordinates `bars` of the triangle the shader set up.
+///
+/// Corners are snapped to fixed point and coverage is decided exactly with edge functions,
+/// stepped incrementally from pixel to pixel. As in Direct3D and OpenGL, samples are taken at
+/// pixel centers, half way between integer positions, and a center on an edge belongs to the
+/// triangle only if it is a top or left edge, so triangles sharing an edge cover each pixel along
+/// it once. Works in 2x2 pixel quads: every pixel of a quad gets barycentric
+/// coordinates, covered or not, so the differences across the quad give each fragment its
+/// derivatives; only covered pixels are depth tested and shaded.
+fn rasterize(
+    clip_verts: &[Vec4f; 3],
+    bars: &[Vec3f; 3],
+    shader: &impl IShader,
+    image: &mut impl RenderTarget,
+    zbuffer: &mut [f32],
     view_bundle: &ViewBundle,
 ) {
     let Viewport = view_bundle.ViewPort.clone();
@@ -77,49 +351,655 @@ pub fn triangle(
         Viewport.clone() * clip_verts[1],
         Viewport.clone() * clip_verts[2],
     ];
-    let pts2 = [
-        proj(pts[0] / pts[0][3]),
-        proj(pts[1] / pts[1][3]),
-        proj(pts[2] / pts[2][3]),
+    let snapped = [
+        snap_clip(&clip_verts[0], &Viewport),
+        snap_clip(&clip_verts[1], &Viewport),
+        snap_clip(&clip_verts[2], &Viewport),
     ];
+    let area = edge_function(snapped[0], snapped[1], snapped[2]);
+    if area == 0 {
+        return;
+    }
+    // edge functions are made positive inside whatever the winding
+    let sign = area.signum();
 
-    let mut bboxmin = Vec2f::new_args(f32::MAX, f32::MAX);
-    let mut bboxmax = Vec2f::new_args(f32::MIN, f32::MIN);
-    let clamp = Vec2f::new_args(
-        (image.get_width() - 1) as f32,
-        (image.get_height() - 1) as f32,
-    );
-    for i in 0..3 {
+    let (width, height) = image.dimensions();
+    let mut bboxmin = [i64::MAX; 2];
+    let mut bboxmax = [i64::MIN; 2];
+    for p in &snapped {
         for j in 0..2 {
-            bboxmin[j] = (bboxmin[j].min(pts2[i][j])).max(0.);
-            bboxmax[j] = (bboxmax[j].max(pts2[i][j])).min(clamp[j]);
+            bboxmin[j] = bboxmin[j].min(p[j]);
+            bboxmax[j] = bboxmax[j].max(p[j]);
+        }
+    }
+    // pixels whose centers, half way between integer positions, are in the bounding box
+    let half = SUBPIXEL / 2;
+    let xmin = (bboxmin[0] - half + SUBPIXEL - 1)
+        .div_euclid(SUBPIXEL)
+        .max(0) as i32;
+    let ymin = (bboxmin[1] - half + SUBPIXEL - 1)
+        .div_euclid(SUBPIXEL)
+        .max(0) as i32;
+    let xmax = (bboxmax[0] - half)
+        .div_euclid(SUBPIXEL)
+        .min(width as i64 - 1) as i32;
+    let ymax = (bboxmax[1] - half)
+        .div_euclid(SUBPIXEL)
+        .min(height as i64 - 1) as i32;
+    if xmin > xmax || ymin > ymax {
+        return;
+    }
+
+    // quads start on even pixels
+    let (x0, y0) = (xmin & !1, ymin & !1);
+    // edge i faces corner i; its function there is the area, so it gives barycentric i
+    let mut row_start = [0; 3];
+    let mut step_x = [0; 3];
+    let mut step_y = [0; 3];
+    let mut bias = [0; 3];
+    for i in 0..3 {
+        let (a, b) = (snapped[(i + 1) % 3], snapped[(i + 2) % 3]);
+        let center = [x0 as i64 * SUBPIXEL + half, y0 as i64 * SUBPIXEL + half];
+        row_start[i] = sign * edge_function(a, b, center);
+        step_x[i] = -sign * (b[1] - a[1]) * SUBPIXEL;
+        step_y[i] = sign * (b[0] - a[0]) * SUBPIXEL;
+        // going counter-clockwise, left edges point down and top edges point left
+        let (dx, dy) = (sign * (b[0] - a[0]), sign * (b[1] - a[1]));
+        let top_left = dy < 0 || (dy == 0 && dx < 0);
+        bias[i] = if top_left { 0 } else { -1 };
+    }
+    let area = (sign * area) as f32;
+
+    // depth after the perspective divide is affine in screen space
+    let depths = Vec3f::new_args(
+        clip_verts[0][2] / clip_verts[0][3],
+        clip_verts[1][2] / clip_verts[1][3],
+        clip_verts[2][2] / clip_verts[2][3],
+    );
+    for qy in (y0..=ymax).step_by(2) {
+        let mut quad = row_start;
+        for qx in (x0..=xmax).step_by(2) {
+            // top-left, top-right, bottom-left, bottom-right
+            let mut covered = [true; 4];
+            let mut bc_screen = [Vec3f::new_args(0., 0., 0.); 4];
+            let mut bc_clip = [Vec3f::new_args(0., 0., 0.); 4];
+            for i in 0..4 {
+                let mut e = [0.; 3];
+                for k in 0..3 {
+                    let mut w = quad[k];
+                    if i & 1 != 0 {
+                        w += step_x[k];
+                    }
+                    if i & 2 != 0 {
+                        w += step_y[k];
+                    }
+                    covered[i] &= w + bias[k] >= 0;
+                    e[k] = w as f32 / area;
+                }
+                bc_screen[i] = Vec3f::new_args(e[0], e[1], e[2]);
+                let bc = Vec3f::new_args(
+                    bc_screen[i].x / pts[0][3],
+                    bc_screen[i].y / pts[1][3],
+                    bc_screen[i].z / pts[2][3],
+                );
+                let bc = bc / (bc.x + bc.y + bc.z);
+                bc_clip[i] = bars[0] * bc.x + bars[1] * bc.y + bars[2] * bc.z;
+            }
+            for k in 0..3 {
+                quad[k] += 2 * step_x[k];
+            }
+            for i in 0..4 {
+                let (row, col) = (i & 2, i & 1);
+                let deriv = QuadDerivatives {
+                    dx: bc_clip[row + 1] - bc_clip[row],
+                    dy: bc_clip[col + 2] - bc_clip[col],
+                };
+                let (x, y) = (qx + (i as i32 & 1), qy + (i as i32 >> 1));
+                if !covered[i] || x > xmax || y > ymax || x < xmin || y < ymin {
+                    continue;
+                }
+                let frag_depth = depths * bc_screen[i];
+                if frag_depth > zbuffer[(x + y * width) as usize] {
+                    continue;
+                }
+                let (discard, color) = shader.fragment(bc_clip[i], &deriv);
+                if discard {
+                    continue;
+                }
+                zbuffer[(x + y * width) as usize] = frag_depth;
+                image.write_fragment(x, y, &color);
+            }
         }
+        for k in 0..3 {
+            row_start[k] += 2 * step_y[k];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Records the barycentrics and derivatives of every shaded fragment.
+    struct Recorder {
+        fragments: RefCell<Vec<(Vec3f, QuadDerivatives)>>,
+    }
+
+    impl IShader for Recorder {
+        fn vertex(&mut self, _iface: i32, _nthvert: i32, _view_bundle: &ViewBundle) -> Vec4f {
+            Vec4f::new()
+        }
+
+        fn fragment(&self, bar: Vec3f, deriv: &QuadDerivatives) -> (bool, Vec4f) {
+            self.fragments.borrow_mut().push((bar, *deriv));
+            (false, Vec4f::new_args(1., 1., 1., 1.))
+        }
+    }
+
+    fn identity() -> Matrix {
+        viewport(-1, -1, 2, 2)
+    }
+
+    fn transform(m: &Matrix, v: [f32; 3]) -> [f32; 3] {
+        let c = m.clone() * embed(&Vec3f::new_args(v[0], v[1], v[2]), None);
+        [c[0] / c[3], c[1] / c[3], c[2] / c[3]]
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        for k in 0..3 {
+            assert!((a[k] - b[k]).abs() < 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn projections_map_to_clip_space() {
+        // 90 degrees vertically and twice as wide, near 1, far 10
+        let p = perspective(std::f32::consts::FRAC_PI_2, 2., 1., 10.);
+        assert_close(transform(&p, [0., 0., -1.]), [0., 0., 0.]);
+        assert_close(transform(&p, [0., 0., -10.]), [0., 0., 1.]);
+        assert_close(transform(&p, [2., 1., -1.]), [1., 1., 0.]);
+        assert_close(transform(&p, [-8., -4., -4.]), [-1., -1., 1. / 0.9 * 0.75]);
+        // w is the distance in front of the eye
+        assert_eq!((p.clone() * Vec4f::new_args(0., 0., -3., 1.))[3], 3.);
+
+        let o = orthographic(-2., 4., -1., 1., 0.5, 2.5);
+        assert_close(transform(&o, [-2., -1., -0.5]), [-1., -1., 0.]);
+        assert_close(transform(&o, [4., 1., -2.5]), [1., 1., 1.]);
+        assert_close(transform(&o, [1., 0., -1.5]), [0., 0., 0.5]);
     }
 
-    for x in (bboxmin.x as i32)..=(bboxmax.x as i32) {
-        for y in (bboxmin.y as i32)..=(bboxmax.y as i32) {
-            let bc_screen = barycentric(&pts2, &Vec2f::new_args(x as f32, y as f32));
-            let mut bc_clip = Vec3f::new_args(
-                bc_screen.x / pts[0][3],
-                bc_screen.y / pts[1][3],
-                bc_screen.z / pts[2][3],
+    #[test]
+    fn lookat_faces_down_negative_z() {
+        let m = lookat(
+            Vec3f::new_args(0., 0., 5.),
+            Vec3f::new_args(0., 0., 0.),
+            Vec3f::new_args(0., 1., 0.),
+        );
+        assert_close(transform(&m, [0., 0., 0.]), [0., 0., -5.]);
+        assert_close(transform(&m, [1., 2., 0.]), [1., 2., -5.]);
+        let m = lookat(
+            Vec3f::new_args(3., 0., 0.),
+            Vec3f::new_args(0., 0., 0.),
+            Vec3f::new_args(0., 1., 0.),
+        );
+        // looking along -x, world -z is to the right
+        assert_close(transform(&m, [0., 0., -1.]), [1., 0., -3.]);
+    }
+
+    /// Outputs the interpolated view space position as the color.
+    struct Positions {
+        view: [Vec3f; 3],
+    }
+
+    impl IShader for Positions {
+        fn vertex(&mut self, _iface: i32, _nthvert: i32, _view_bundle: &ViewBundle) -> Vec4f {
+            Vec4f::new()
+        }
+
+        fn fragment(&self, bar: Vec3f, _deriv: &QuadDerivatives) -> (bool, Vec4f) {
+            let p = self.view[0] * bar.x + self.view[1] * bar.y + self.view[2] * bar.z;
+            (false, Vec4f::new_args(p.x, p.y, p.z, 1.))
+        }
+    }
+
+    #[test]
+    fn clips_against_the_near_plane() {
+        let p = perspective(std::f32::consts::FRAC_PI_2, 1., 1., 10.);
+        // a floor reaching from the far distance to behind the eye
+        let view = [
+            Vec3f::new_args(-4., -1., -8.),
+            Vec3f::new_args(0., -1., 2.),
+            Vec3f::new_args(4., -1., -8.),
+        ];
+        let clip: Vec<Vec4f> = view.iter().map(|v| p.clone() * embed(v, None)).collect();
+
+        let poly = clip_triangle(&clip, Clipping::Near);
+        assert_eq!(poly.len(), 4);
+        for (v, bar) in &poly {
+            assert!(v[2] >= -1e-6 && v[3] > 0.);
+            assert!((bar.x + bar.y + bar.z - 1.).abs() < 1e-6);
+            // the clip position is the same combination of the corners as the varyings
+            for k in 0..4 {
+                let expected = clip[0][k] * bar.x + clip[1][k] * bar.y + clip[2][k] * bar.z;
+                assert!((v[k] - expected).abs() < 1e-4);
+            }
+        }
+        // nothing to clip, or everything
+        let shifted = |dz: f32| -> Vec<Vec4f> {
+            view.iter()
+                .map(|v| p.clone() * embed(&(*v + Vec3f::new_args(0., 0., dz)), None))
+                .collect()
+        };
+        assert_eq!(clip_triangle(&shifted(-4.), Clipping::Near).len(), 3);
+        assert!(clip_triangle(&shifted(12.), Clipping::Near).is_empty());
+
+        let view_bundle = ViewBundle {
+            ModelView: identity(),
+            ViewPort: viewport(0, 0, 32, 32),
+            Projection: p.clone(),
+        };
+        let shader = Positions { view };
+        let mut image = FloatImage::new_dimensions(32, 32, 4);
+        let mut zbuffer = vec![1.; 32 * 32];
+        triangle(
+            &clip,
+            &shader,
+            &mut image,
+            &mut zbuffer,
+            &view_bundle,
+            &RenderState::default(),
+        );
+        let mut covered = 0;
+        for y in 0..32 {
+            for x in 0..32 {
+                let c = image.get(x, y).unwrap();
+                if c[3] == 0. {
+                    continue;
+                }
+                covered += 1;
+                // each fragment sees the point of the floor that projects onto it
+                let s = view_bundle.ViewPort.clone()
+                    * (p.clone() * Vec4f::new_args(c[0], c[1], c[2], 1.));
+                assert!(
+                    (s[0] / s[3] - (x as f32 + 0.5)).abs() < 1e-2,
+                    "{} {:?}",
+                    x,
+                    c
+                );
+                assert!(
+                    (s[1] / s[3] - (y as f32 + 0.5)).abs() < 1e-2,
+                    "{} {:?}",
+                    y,
+                    c
+                );
+            }
+        }
+        // the floor fills the bottom of the view up to the horizon
+        assert!(covered > 32 * 8);
+        assert_eq!(image.get(16, 0).unwrap()[3], 1.);
+        assert_eq!(image.get(16, 20).unwrap()[3], 0.);
+    }
+
+    #[test]
+    fn frustum_clipping_keeps_coverage() {
+        // a triangle much larger than the screen, partly beyond the far plane
+        let verts = [
+            Vec4f::new_args(-50., -50., 0.5, 1.),
+            Vec4f::new_args(50., -50., 0.5, 1.),
+            Vec4f::new_args(0., 50., 1.5, 1.),
+        ];
+        assert!(clip_triangle(&verts, Clipping::Frustum).len() > 3);
+        let view_bundle = ViewBundle {
+            ModelView: identity(),
+            ViewPort: viewport(0, 0, 16, 16),
+            Projection: identity(),
+        };
+        let mut coverage = Vec::new();
+        for &clipping in &[Clipping::Near, Clipping::Frustum] {
+            let shader = Recorder {
+                fragments: RefCell::new(Vec::new()),
+            };
+            let mut image = FloatImage::new_dimensions(16, 16, 1);
+            let mut zbuffer = vec![1.; 16 * 16];
+            let state = RenderState {
+                clipping,
+                ..RenderState::default()
+            };
+            triangle(
+                &verts,
+                &shader,
+                &mut image,
+                &mut zbuffer,
+                &view_bundle,
+                &state,
             );
-            bc_clip = bc_clip / (bc_clip.x + bc_clip.y + bc_clip.z);
-            let frag_depth =
-                Vec3f::new_args(clip_verts[0][2], clip_verts[1][2], clip_verts[2][2]) * bc_clip;
-            if bc_screen.x < 0.
-                || bc_screen.y < 0.
-                || bc_screen.z < 0.
-                || frag_depth > zbuffer[(x + y * image.get_width()) as usize]
-            {
-                continue;
+            coverage.push(image);
+        }
+        assert_eq!(coverage[0], coverage[1]);
+        // the far plane cuts at the same depth either way
+        assert_eq!(coverage[0].get(8, 0).unwrap()[0], 1.);
+        assert_eq!(coverage[0].get(8, 15).unwrap()[0], 0.);
+    }
+
+    #[test]
+    fn culls_by_winding() {
+        let view_bundle = ViewBundle {
+            ModelView: identity(),
+            ViewPort: viewport(0, 0, 8, 8),
+            Projection: identity(),
+        };
+        let ccw = [
+            Vec4f::new_args(-1., -1., 0.5, 1.),
+            Vec4f::new_args(1., -1., 0.5, 1.),
+            Vec4f::new_args(0., 1., 0.5, 1.),
+        ];
+        let cw = [ccw[0], ccw[2], ccw[1]];
+        let flat = [ccw[0], ccw[1], ccw[1]];
+        let draw = |verts: &[Vec4f; 3], cull_mode, front_face| {
+            let shader = Recorder {
+                fragments: RefCell::new(Vec::new()),
+            };
+            let mut image = FloatImage::new_dimensions(8, 8, 1);
+            let mut zbuffer = vec![1.; 8 * 8];
+            let state = RenderState {
+                cull_mode,
+                front_face,
+                ..RenderState::default()
+            };
+            let outcome = triangle(
+                verts,
+                &shader,
+                &mut image,
+                &mut zbuffer,
+                &view_bundle,
+                &state,
+            );
+            let fragments = shader.fragments.borrow().len();
+            (outcome, fragments)
+        };
+
+        use CullMode::*;
+        use FrontFace::*;
+        let drawn = draw(&ccw, None, Ccw);
+        assert_eq!(drawn.0, TriangleOutcome::Drawn);
+        assert!(drawn.1 > 0);
+        // either winding covers the same pixels when drawn
+        assert_eq!(draw(&cw, None, Ccw), drawn);
+        assert_eq!(draw(&cw, Back, Cw), drawn);
+        assert_eq!(draw(&cw, Front, Ccw), drawn);
+        assert_eq!(draw(&cw, Back, Ccw), (TriangleOutcome::Culled, 0));
+        assert_eq!(draw(&ccw, Front, Ccw), (TriangleOutcome::Culled, 0));
+        assert_eq!(draw(&ccw, Back, Cw), (TriangleOutcome::Culled, 0));
+        // nothing to draw either way
+        assert_eq!(draw(&flat, None, Ccw), (TriangleOutcome::Culled, 0));
+        // a sliver a ten-thousandth of a pixel wide, which snapping widens to a subpixel, along
+        // a left edge through the pixel centers of the diagonal
+        let clip = |x: f32, y: f32| Vec4f::new_args(x / 4. - 1., y / 4. - 1., 0.5, 1.);
+        let (x0, x1) = (7.5 + 0.5 / 256. - 5e-5, 7.5 + 0.5 / 256. + 5e-5);
+        let sliver = [clip(0.5, 0.5), clip(x0, 7.5), clip(x1, 7.5)];
+        assert_eq!(draw(&sliver, None, Ccw), (TriangleOutcome::Drawn, 7));
+        let behind = [ccw[0] / -1., ccw[1] / -1., ccw[2] / -1.];
+        assert_eq!(draw(&behind, None, Ccw), (TriangleOutcome::Clipped, 0));
+    }
+
+    /// Counts how often each pixel is written.
+    struct Overdraw {
+        size: i32,
+        counts: Vec<u32>,
+    }
+
+    impl RenderTarget for Overdraw {
+        fn dimensions(&self) -> (i32, i32) {
+            (self.size, self.size)
+        }
+
+        fn write_fragment(&mut self, x: i32, y: i32, _color: &Vec4f) {
+            self.counts[(x + y * self.size) as usize] += 1;
+        }
+    }
+
+    /// Draws a triangle given in screen coordinates of a square target, without culling.
+    fn draw_screen(tri: [[f32; 2]; 3], target: &mut Overdraw) -> TriangleOutcome {
+        let half = target.size as f32 / 2.;
+        let view_bundle = ViewBundle {
+            ModelView: identity(),
+            ViewPort: viewport(0, 0, target.size, target.size),
+            Projection: identity(),
+        };
+        let verts: Vec<Vec4f> = tri
+            .iter()
+            .map(|p| Vec4f::new_args((p[0] - half) / half, (p[1] - half) / half, 0.5, 1.))
+            .collect();
+        let shader = Recorder {
+            fragments: RefCell::new(Vec::new()),
+        };
+        let mut zbuffer = vec![1.; target.counts.len()];
+        let state = RenderState {
+            cull_mode: CullMode::None,
+            ..RenderState::default()
+        };
+        triangle(&verts, &shader, target, &mut zbuffer, &view_bundle, &state)
+    }
+
+    /// Pixels whose centers a triangle covers, tested one at a time in f64 on the snapped corners,
+    /// which is exact, with the top-left rule spelled out.
+    fn reference_coverage(tri: [[f32; 2]; 3], size: i32) -> Vec<u32> {
+        let snap = |v: f32| (v as f64 * 256.).round() / 256.;
+        let mut p: Vec<[f64; 2]> = tri.iter().map(|v| [snap(v[0]), snap(v[1])]).collect();
+        let cross = |a: [f64; 2], b: [f64; 2], c: [f64; 2]| {
+            (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+        };
+        let area = cross(p[0], p[1], p[2]);
+        if area < 0. {
+            p.swap(1, 2);
+        }
+        let mut counts = vec![0; (size * size) as usize];
+        for y in 0..size {
+            for x in 0..size {
+                let c = [x as f64 + 0.5, y as f64 + 0.5];
+                let inside = (0..3).all(|i| {
+                    let (a, b) = (p[i], p[(i + 1) % 3]);
+                    let e = cross(a, b, c);
+                    let top = a[1] == b[1] && b[0] < a[0];
+                    let left = b[1] < a[1];
+                    e > 0. || (e == 0. && (top || left))
+                });
+                if inside && area != 0. {
+                    counts[(x + y * size) as usize] = 1;
+                }
             }
-            let (discard, color) = shader.fragment(bc_clip);
-            if discard {
-                continue;
+        }
+        counts
+    }
+
+    /// Deterministic numbers in [0, 1).
+    fn lcg(seed: &mut u32) -> f32 {
+        *seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        (*seed >> 8) as f32 / (1 << 24) as f32
+    }
+
+    #[test]
+    fn coverage_matches_reference() {
+        let mut seed = 7;
+        // corners on a 1/1024 grid, which snapping rounds, and on a 1/2 grid, which puts pixel
+        // centers right on edges
+        for &grid in &[1024., 2.] {
+            for _ in 0..200 {
+                let mut tri = [[0.; 2]; 3];
+                for v in &mut tri {
+                    for c in v.iter_mut() {
+                        *c = (lcg(&mut seed) * 40. - 4.) * grid;
+                        *c = c.round() / grid;
+                    }
+                }
+                let mut target = Overdraw {
+                    size: 32,
+                    counts: vec![0; 32 * 32],
+                };
+                let outcome = draw_screen(tri, &mut target);
+                let expected = reference_coverage(tri, 32);
+                assert_eq!(target.counts, expected, "{:?}", tri);
+                if outcome != TriangleOutcome::Drawn {
+                    assert!(expected.iter().all(|&c| c == 0));
+                }
             }
-            zbuffer[(x + y * image.get_width()) as usize] = frag_depth;
-            image.set(x, y, &color);
         }
     }
+
+    #[test]
+    fn slivers_match_reference() {
+        let mut seed = 11;
+        let mut covered = 0;
+        for _ in 0..300 {
+            // from a pixel center through more of them to b, with c a ten-thousandth of a pixel
+            // from b across a rounding boundary of the subpixel grid: far less than 1e-3 of area
+            // before snapping, a subpixel wide after
+            let mut int = |n: f32| (lcg(&mut seed) * n) as i32;
+            let a = [int(28.) as f32 + 2.5, int(28.) as f32 + 2.5];
+            let (i, j) = (int(7.) - 3, int(5.) - 2);
+            let m = (int(2.) + 1) as f32;
+            let mut b = [a[0] + m * i as f32, a[1] + m * j as f32];
+            let mut c = b;
+            let k = if j.abs() > i.abs() { 0 } else { 1 };
+            let side = if int(2.) == 0 { 1. } else { -1. };
+            b[k] += side * (0.5 / 256. - 5e-5);
+            c[k] += side * (0.5 / 256. + 5e-5);
+            let tri = [a, b, c];
+            let mut target = Overdraw {
+                size: 32,
+                counts: vec![0; 32 * 32],
+            };
+            draw_screen(tri, &mut target);
+            let expected = reference_coverage(tri, 32);
+            assert_eq!(target.counts, expected, "{:?}", tri);
+            covered += expected.iter().sum::<u32>();
+        }
+        assert!(covered > 0);
+
+        // a sliver between two triangles of a strip, none of them drawn twice or left out
+        let (x0, x1) = (16.5 + 0.5 / 256. - 1e-5, 16.5 + 0.5 / 256. + 1e-5);
+        let strip = [
+            [[0.5, 0.5], [x0, 30.5], [0.5, 30.5]],
+            [[0.5, 0.5], [x1, 30.5], [x0, 30.5]],
+            [[0.5, 0.5], [30.5, 0.5], [x1, 30.5]],
+        ];
+        let mut target = Overdraw {
+            size: 32,
+            counts: vec![0; 32 * 32],
+        };
+        let mut expected = vec![0; 32 * 32];
+        for &tri in &strip {
+            assert_eq!(draw_screen(tri, &mut target), TriangleOutcome::Drawn);
+            for (e, c) in expected.iter_mut().zip(reference_coverage(tri, 32)) {
+                *e += c;
+            }
+        }
+        assert_eq!(target.counts, expected);
+        assert!(expected.iter().all(|&c| c <= 1));
+        assert_ne!(reference_coverage(strip[1], 32), vec![0; 32 * 32]);
+    }
+
+    #[test]
+    fn shared_edges_cover_pixels_once() {
+        let mut seed = 3;
+        let mut meshes: Vec<Vec<[[f32; 2]; 3]>> = Vec::new();
+        // grids reaching past the screen, with corners on pixel corners or moved about
+        for &jitter in &[0., 3.] {
+            let mut corner = [[[0.; 2]; 7]; 7];
+            for (j, row) in corner.iter_mut().enumerate() {
+                for (i, p) in row.iter_mut().enumerate() {
+                    let inner = (1..6).contains(&i) && (1..6).contains(&j);
+                    for (k, c) in p.iter_mut().enumerate() {
+                        let at = [i, j][k] as f32 * 8. - 8.;
+                        let offset = if inner {
+                            (lcg(&mut seed) * 2. - 1.) * jitter
+                        } else {
+                            0.
+                        };
+                        *c = ((at + offset) * 1024.).round() / 1024.;
+                    }
+                }
+            }
+            let mut mesh = Vec::new();
+            for j in 0..6 {
+                for i in 0..6 {
+                    let (a, b) = (corner[j][i], corner[j][i + 1]);
+                    let (c, d) = (corner[j + 1][i + 1], corner[j + 1][i]);
+                    // both diagonals, and both windings
+                    if (i + j) % 2 == 0 {
+                        mesh.extend_from_slice(&[[a, b, c], [a, d, c]]);
+                    } else {
+                        mesh.extend_from_slice(&[[a, b, d], [b, c, d]]);
+                    }
+                }
+            }
+            meshes.push(mesh);
+        }
+        // fans around a pixel corner and a pixel center
+        for &center in &[[16., 16.], [16.5, 16.5]] {
+            let ring: Vec<[f32; 2]> = (0..12)
+                .map(|k| {
+                    let angle = k as f32 * std::f32::consts::PI / 6.;
+                    [center[0] + 40. * angle.cos(), center[1] + 40. * angle.sin()]
+                })
+                .collect();
+            meshes.push(
+                (0..12)
+                    .map(|k| [center, ring[k], ring[(k + 1) % 12]])
+                    .collect(),
+            );
+        }
+
+        for mesh in meshes {
+            let mut target = Overdraw {
+                size: 32,
+                counts: vec![0; 32 * 32],
+            };
+            for tri in mesh {
+                draw_screen(tri, &mut target);
+            }
+            assert!(target.counts.iter().all(|&c| c == 1), "{:?}", target.counts);
+        }
+    }
+
+    #[test]
+    fn quads_provide_barycentric_derivatives() {
+        // screen-space triangle with w = 1 everywhere, so barycentrics are affine in x and y
+        let verts = [
+            Vec4f::new_args(1., 1., 0., 1.),
+            Vec4f::new_args(41., 1., 0., 1.),
+            Vec4f::new_args(1., 21., 0., 1.),
+        ];
+        let view_bundle = ViewBundle {
+            ModelView: identity(),
+            ViewPort: identity(),
+            Projection: identity(),
+        };
+        let shader = Recorder {
+            fragments: RefCell::new(Vec::new()),
+        };
+        let mut image = FloatImage::new_dimensions(64, 64, 1);
+        let mut zbuffer = vec![f32::MAX; 64 * 64];
+        triangle(
+            &verts,
+            &shader,
+            &mut image,
+            &mut zbuffer,
+            &view_bundle,
+            &RenderState::default(),
+        );
+
+        let fragments = shader.fragments.into_inner();
+        // pixel centers right of x = 1, above y = 1 and under the hypotenuse: 41 - 2 y in row y
+        assert_eq!(fragments.len(), (1..=20).map(|y| 41 - 2 * y).sum::<usize>());
+        for (_, d) in &fragments {
+            assert!((d.dx.x + 1. / 40.).abs() < 1e-4 && (d.dx.y - 1. / 40.).abs() < 1e-4);
+            assert!(d.dx.z.abs() < 1e-4);
+            assert!((d.dy.x + 1. / 20.).abs() < 1e-4 && (d.dy.z - 1. / 20.).abs() < 1e-4);
+            assert!(d.dy.y.abs() < 1e-4);
+        }
+        assert_eq!(image.get(1, 1).unwrap()[0], 1.);
+        assert_eq!(image.get(0, 0).unwrap()[0], 0.);
+    }
 }
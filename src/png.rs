@@ -0,0 +1,769 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Write};
+
+use crate::tga::{DecodeLimits, TGAFormat, TGAImage};
+
+/*
+    PNG encoder and decoder for TGAImage
+
+    Writes 8-bit grayscale, RGB and RGBA images with per-row
+    adaptive filtering. Reads every standard color type and bit
+    depth, interlaced or not, and expands it to the closest
+    TGAImage format. The zlib streams come from miniz_oxide,
+    the chunk CRCs are computed here.
+*/
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_TYPE_GRAYSCALE: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAYSCALE_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// Adam7 passes as (x start, y start, x step, y step).
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Reasons a PNG file can fail to decode. Offsets are in bytes from the start of the file.
+#[derive(Debug)]
+pub enum PngError {
+    Io(io::Error),
+    BadSignature,
+    /// The data ended inside the chunk starting at `offset`.
+    Truncated {
+        offset: u64,
+    },
+    BadCrc {
+        offset: u64,
+    },
+    /// A chunk the decoder needs is missing, or a critical chunk it doesn't know is present.
+    BadChunk {
+        kind: [u8; 4],
+    },
+    BadDimensions {
+        width: u32,
+        height: u32,
+    },
+    /// The image exceeds the `DecodeLimits` it was decoded with.
+    TooLarge {
+        width: u32,
+        height: u32,
+    },
+    UnsupportedFormat {
+        color_type: u8,
+        bit_depth: u8,
+    },
+    BadCompression,
+    BadFilter {
+        filter: u8,
+    },
+    BadPaletteIndex {
+        index: u8,
+    },
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Io(e) => write!(f, "{e}"),
+            PngError::BadSignature => write!(f, "not a PNG file"),
+            PngError::Truncated { offset } => write!(f, "data truncated in chunk at byte {offset}"),
+            PngError::BadCrc { offset } => write!(f, "CRC mismatch in chunk at byte {offset}"),
+            PngError::BadChunk { kind } => {
+                write!(
+                    f,
+                    "missing or unexpected {} chunk",
+                    String::from_utf8_lossy(kind)
+                )
+            }
+            PngError::BadDimensions { width, height } => {
+                write!(f, "bad image dimensions {width}x{height}")
+            }
+            PngError::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "image dimensions {width}x{height} exceed the decoding limits"
+                )
+            }
+            PngError::UnsupportedFormat {
+                color_type,
+                bit_depth,
+            } => write!(
+                f,
+                "unsupported color type {color_type} at bit depth {bit_depth}"
+            ),
+            PngError::BadCompression => write!(f, "corrupt zlib stream"),
+            PngError::BadFilter { filter } => write!(f, "unknown scanline filter {filter}"),
+            PngError::BadPaletteIndex { index } => {
+                write!(f, "palette index {index} out of range")
+            }
+        }
+    }
+}
+
+impl std::error::Error for PngError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PngError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+    fn from(e: io::Error) -> Self {
+        PngError::Io(e)
+    }
+}
+
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        Self {
+            table,
+            crc: 0xffffffff,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.crc = self.table[((self.crc ^ b as u32) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xffffffff
+    }
+}
+
+pub fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc.finish().to_be_bytes())
+}
+
+/// Applies filter `kind` to `row` into `out`, given the unfiltered previous row.
+fn filter_row(kind: u8, row: &[u8], prev: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.clear();
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        out.push(match kind {
+            0 => row[i],
+            1 => row[i].wrapping_sub(a),
+            2 => row[i].wrapping_sub(b),
+            3 => row[i].wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+            _ => row[i].wrapping_sub(paeth(a, b, c)),
+        });
+    }
+}
+
+/// Reverses filter `kind` on `row` in place, given the already reconstructed previous row.
+fn unfilter_row(kind: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Result<(), PngError> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        row[i] = row[i].wrapping_add(match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(PngError::BadFilter { filter: kind }),
+        });
+    }
+    Ok(())
+}
+
+/// Reads the `i`-th sample of a scanline packed at `depth` bits per sample.
+fn sample(row: &[u8], i: usize, depth: u8) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[2 * i], row[2 * i + 1]]),
+        8 => row[i] as u16,
+        _ => {
+            let bit = i * depth as usize;
+            let shift = 8 - depth as usize - bit % 8;
+            ((row[bit / 8] >> shift) & ((1 << depth) - 1)) as u16
+        }
+    }
+}
+
+fn scale_to_u8(v: u16, depth: u8) -> u8 {
+    match depth {
+        16 => (v >> 8) as u8,
+        8 => v as u8,
+        _ => (v as u32 * 255 / ((1 << depth) - 1)) as u8,
+    }
+}
+
+struct PngHeader {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_RGB => 3,
+            COLOR_TYPE_GRAYSCALE_ALPHA => 2,
+            COLOR_TYPE_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    /// Bytes in one scanline of `width` pixels, filter byte excluded.
+    fn stride(&self, width: usize) -> usize {
+        (width * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// The (x start, y start, x step, y step) of each pass the image is stored in.
+    fn passes(&self) -> &'static [(usize, usize, usize, usize)] {
+        if self.interlaced {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        }
+    }
+}
+
+impl TGAImage {
+    pub fn read_png_file(&mut self, filename: &str) -> Result<(), PngError> {
+        *self = TGAImage::from_png_reader(File::open(filename)?)?;
+        Ok(())
+    }
+
+    pub fn from_png_reader<R: Read>(reader: R) -> Result<TGAImage, PngError> {
+        TGAImage::from_png_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    pub fn from_png_reader_with_limits<R: Read>(
+        mut reader: R,
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, PngError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        TGAImage::from_png_bytes_with_limits(&bytes, limits)
+    }
+
+    /// Decodes a PNG into a grayscale, RGB or RGBA image. 16-bit samples keep their high
+    /// byte, and gray+alpha, transparent palettes and tRNS color keys turn into RGBA.
+    pub fn from_png_bytes(bytes: &[u8]) -> Result<TGAImage, PngError> {
+        TGAImage::from_png_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    pub fn from_png_bytes_with_limits(
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, PngError> {
+        if bytes.len() < 8 || bytes[..8] != PNG_SIGNATURE {
+            return Err(PngError::BadSignature);
+        }
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut transparency: &[u8] = &[];
+        let mut idat = Vec::new();
+        let mut pos = 8;
+        loop {
+            let offset = pos as u64;
+            if bytes.len() - pos < 12 {
+                return Err(PngError::Truncated { offset });
+            }
+            let len =
+                u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+                    as usize;
+            if bytes.len() - pos - 12 < len {
+                return Err(PngError::Truncated { offset });
+            }
+            let kind = [
+                bytes[pos + 4],
+                bytes[pos + 5],
+                bytes[pos + 6],
+                bytes[pos + 7],
+            ];
+            let data = &bytes[pos + 8..pos + 8 + len];
+            let stored_crc = &bytes[pos + 8 + len..pos + 12 + len];
+            let mut crc = Crc32::new();
+            crc.update(&kind);
+            crc.update(data);
+            if crc.finish().to_be_bytes() != stored_crc {
+                return Err(PngError::BadCrc { offset });
+            }
+            pos += 12 + len;
+
+            match &kind {
+                b"IHDR" if data.len() == 13 => {
+                    let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+                    let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+                    if width == 0 || height == 0 {
+                        return Err(PngError::BadDimensions { width, height });
+                    }
+                    if width > limits.max_width as u32 || height > limits.max_height as u32 {
+                        return Err(PngError::TooLarge { width, height });
+                    }
+                    let (bit_depth, color_type) = (data[8], data[9]);
+                    let valid = match color_type {
+                        COLOR_TYPE_GRAYSCALE => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+                        COLOR_TYPE_PALETTE => matches!(bit_depth, 1 | 2 | 4 | 8),
+                        COLOR_TYPE_RGB | COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_RGBA => {
+                            matches!(bit_depth, 8 | 16)
+                        }
+                        _ => false,
+                    };
+                    if !valid || data[10] != 0 || data[11] != 0 || data[12] > 1 {
+                        return Err(PngError::UnsupportedFormat {
+                            color_type,
+                            bit_depth,
+                        });
+                    }
+                    header = Some(PngHeader {
+                        width: width as usize,
+                        height: height as usize,
+                        bit_depth,
+                        color_type,
+                        interlaced: data[12] == 1,
+                    });
+                }
+                b"PLTE" => palette = data,
+                b"tRNS" => transparency = data,
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => break,
+                _ if kind[0] & 0x20 == 0 => return Err(PngError::BadChunk { kind }),
+                _ => {}
+            }
+        }
+        let header = header.ok_or(PngError::BadChunk { kind: *b"IHDR" })?;
+        if header.color_type == COLOR_TYPE_PALETTE && palette.is_empty() {
+            return Err(PngError::BadChunk { kind: *b"PLTE" });
+        }
+
+        // the exact size of the filtered scanlines, which bounds the inflated data
+        let mut expected = 0;
+        let passes = header.passes();
+        for &(x0, y0, dx, dy) in passes {
+            let pw = (header.width + dx - 1 - x0) / dx;
+            let ph = (header.height + dy - 1 - y0) / dy;
+            if pw > 0 && ph > 0 {
+                expected += ph * (1 + header.stride(pw));
+            }
+        }
+        let mut raw = decompress_to_vec_zlib_with_limit(&idat, expected)
+            .map_err(|_| PngError::BadCompression)?;
+        if raw.len() < expected {
+            return Err(PngError::BadCompression);
+        }
+
+        let has_alpha = matches!(
+            header.color_type,
+            COLOR_TYPE_GRAYSCALE_ALPHA | COLOR_TYPE_RGBA
+        ) || !transparency.is_empty();
+        let bytespp = if has_alpha {
+            TGAFormat::RGBA
+        } else if header.color_type == COLOR_TYPE_GRAYSCALE {
+            TGAFormat::Grayscale
+        } else {
+            TGAFormat::RGB
+        } as usize;
+        let mut img =
+            TGAImage::new_dimensions(header.width as i32, header.height as i32, bytespp as i32);
+        let out = img.buffer_mut().unwrap();
+
+        let depth = header.bit_depth;
+        let channels = header.channels();
+        let filter_bpp = (channels * depth as usize).div_ceil(8);
+        let key = |i: usize| {
+            transparency
+                .get(2 * i..2 * i + 2)
+                .map(|k| u16::from_be_bytes([k[0], k[1]]))
+        };
+        let mut pos = 0;
+        for &(x0, y0, dx, dy) in passes {
+            let pw = (header.width + dx - 1 - x0) / dx;
+            let ph = (header.height + dy - 1 - y0) / dy;
+            if pw == 0 || ph == 0 {
+                continue;
+            }
+            let stride = header.stride(pw);
+            let mut prev = vec![0u8; stride];
+            for py in 0..ph {
+                let filter = raw[pos];
+                let row = &mut raw[pos + 1..pos + 1 + stride];
+                unfilter_row(filter, row, &prev, filter_bpp)?;
+                prev.copy_from_slice(row);
+                pos += 1 + stride;
+
+                for px in 0..pw {
+                    let s = |c: usize| sample(&prev, px * channels + c, depth);
+                    let rgba = match header.color_type {
+                        COLOR_TYPE_GRAYSCALE => {
+                            let v = scale_to_u8(s(0), depth);
+                            let a = if key(0) == Some(s(0)) { 0 } else { 255 };
+                            [v, v, v, a]
+                        }
+                        COLOR_TYPE_RGB => {
+                            let opaque = (0..3).any(|c| key(c) != Some(s(c)));
+                            [
+                                scale_to_u8(s(0), depth),
+                                scale_to_u8(s(1), depth),
+                                scale_to_u8(s(2), depth),
+                                if opaque { 255 } else { 0 },
+                            ]
+                        }
+                        COLOR_TYPE_PALETTE => {
+                            let index = s(0) as usize;
+                            let entry = palette
+                                .get(3 * index..3 * index + 3)
+                                .ok_or(PngError::BadPaletteIndex { index: index as u8 })?;
+                            let a = transparency.get(index).copied().unwrap_or(255);
+                            [entry[0], entry[1], entry[2], a]
+                        }
+                        COLOR_TYPE_GRAYSCALE_ALPHA => {
+                            let v = scale_to_u8(s(0), depth);
+                            [v, v, v, scale_to_u8(s(1), depth)]
+                        }
+                        _ => [
+                            scale_to_u8(s(0), depth),
+                            scale_to_u8(s(1), depth),
+                            scale_to_u8(s(2), depth),
+                            scale_to_u8(s(3), depth),
+                        ],
+                    };
+                    let x = x0 + px * dx;
+                    let y = y0 + py * dy;
+                    let i = (x + y * header.width) * bytespp;
+                    if bytespp == TGAFormat::Grayscale as usize {
+                        out[i] = rgba[0];
+                    } else {
+                        out[i] = rgba[2];
+                        out[i + 1] = rgba[1];
+                        out[i + 2] = rgba[0];
+                        if bytespp == TGAFormat::RGBA as usize {
+                            out[i + 3] = rgba[3];
+                        }
+                    }
+                }
+            }
+        }
+        Ok(img)
+    }
+
+    pub fn write_png(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_png_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn write_png_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let bpp = self.get_bytespp() as usize;
+        let color_type = match self.get_bytespp() {
+            x if x == TGAFormat::Grayscale as i32 => COLOR_TYPE_GRAYSCALE,
+            x if x == TGAFormat::RGB as i32 => COLOR_TYPE_RGB,
+            x if x == TGAFormat::RGBA as i32 => COLOR_TYPE_RGBA,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "only grayscale, RGB and RGBA images can be saved as PNG",
+                ))
+            }
+        };
+        let data = self
+            .buffer()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "image has no data"))?;
+
+        // TGAImage keeps BGR(A), PNG wants RGB(A). Each row gets the filter with the
+        // smallest sum of absolute differences, the usual heuristic from libpng.
+        let stride = self.get_width() as usize * bpp;
+        let mut raw = Vec::with_capacity((stride + 1) * self.get_height() as usize);
+        let mut prev = vec![0u8; stride];
+        let mut row = vec![0u8; stride];
+        let mut candidate = Vec::with_capacity(stride);
+        let mut best = Vec::with_capacity(stride);
+        for line in data.chunks_exact(stride) {
+            row.copy_from_slice(line);
+            if bpp >= 3 {
+                for p in row.chunks_exact_mut(bpp) {
+                    p.swap(0, 2);
+                }
+            }
+            let mut best_kind = 0;
+            let mut best_score = u64::MAX;
+            for kind in 0..5 {
+                filter_row(kind, &row, &prev, bpp, &mut candidate);
+                let score = candidate
+                    .iter()
+                    .map(|&v| (v as i8).unsigned_abs() as u64)
+                    .sum();
+                if score < best_score {
+                    best_score = score;
+                    best_kind = kind;
+                    std::mem::swap(&mut best, &mut candidate);
+                }
+            }
+            raw.push(best_kind);
+            raw.extend_from_slice(&best);
+            std::mem::swap(&mut prev, &mut row);
+        }
+
+        let mut ihdr = Vec::with_capacity(13);
+        ihdr.extend_from_slice(&(self.get_width() as u32).to_be_bytes());
+        ihdr.extend_from_slice(&(self.get_height() as u32).to_be_bytes());
+        // bit depth, color type, compression, filter and interlace methods
+        ihdr.extend_from_slice(&[8, color_type, 0, 0, 0]);
+
+        out.write_all(&PNG_SIGNATURE)?;
+        write_chunk(&mut out, b"IHDR", &ihdr)?;
+        write_chunk(&mut out, b"IDAT", &compress_to_vec_zlib(&raw, 6))?;
+        write_chunk(&mut out, b"IEND", &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TGAColor;
+
+    #[test]
+    fn crc_of_iend() {
+        let mut crc = Crc32::new();
+        crc.update(b"IEND");
+        assert_eq!(crc.finish(), 0xae426082);
+    }
+
+    #[test]
+    fn encodes_rgb_image() {
+        let mut img = TGAImage::new_dimensions(3, 2, TGAFormat::RGB as i32);
+        img.set(1, 0, &TGAColor::new_rgba(10, 20, 30, 255));
+        let mut png = Vec::new();
+        img.write_png_to(&mut png).unwrap();
+
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], 3u32.to_be_bytes());
+        assert_eq!(png[20..24], 2u32.to_be_bytes());
+        assert_eq!(png[24..26], [8, COLOR_TYPE_RGB]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        let idat_len = u32::from_be_bytes([png[33], png[34], png[35], png[36]]) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(&png[41..41 + idat_len]).unwrap();
+        assert_eq!(raw.len(), 2 * (1 + 3 * 3));
+        // no filter on the first row keeps the RGB bytes as they are
+        assert_eq!(raw[0], 0);
+        assert_eq!(raw[1..10], [0, 0, 0, 10, 20, 30, 0, 0, 0]);
+    }
+
+    /// Wraps already filtered scanlines and extra chunks into a PNG stream.
+    fn make_png(
+        width: u32,
+        height: u32,
+        depth: u8,
+        color_type: u8,
+        interlace: u8,
+        chunks: &[(&[u8; 4], &[u8])],
+        raw: &[u8],
+    ) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[depth, color_type, 0, 0, interlace]);
+        let mut png = PNG_SIGNATURE.to_vec();
+        write_chunk(&mut png, b"IHDR", &ihdr).unwrap();
+        for (kind, data) in chunks {
+            write_chunk(&mut png, kind, data).unwrap();
+        }
+        write_chunk(&mut png, b"IDAT", &compress_to_vec_zlib(raw, 6)).unwrap();
+        write_chunk(&mut png, b"IEND", &[]).unwrap();
+        png
+    }
+
+    #[test]
+    fn decodes_own_output() {
+        for &bpp in &[1, 3, 4] {
+            let mut img = TGAImage::new_dimensions(37, 23, bpp);
+            for y in 0..23 {
+                for x in 0..37 {
+                    let v = (x * 7 + y * 13 + (x * y) % 5) as u8;
+                    img.set(
+                        x,
+                        y,
+                        &TGAColor::new_rgba(v, v ^ 0x5a, v.wrapping_mul(3), 255 - v),
+                    );
+                }
+            }
+            let mut png = Vec::new();
+            img.write_png_to(&mut png).unwrap();
+            let read = TGAImage::from_png_bytes(&png).unwrap();
+            assert_eq!(read.get_bytespp(), bpp);
+            assert_eq!(read.buffer(), img.buffer());
+        }
+    }
+
+    #[test]
+    fn decodes_adam7_interlacing() {
+        // 3x3 gray image with value 10 * y + x, stored as passes 1, 4, 5, 6 and 7
+        let raw = [
+            0, 0, //
+            0, 2, //
+            0, 20, 22, //
+            0, 1, 0, 21, //
+            0, 10, 11, 12,
+        ];
+        let png = make_png(3, 3, 8, COLOR_TYPE_GRAYSCALE, 1, &[], &raw);
+        let img = TGAImage::from_png_bytes(&png).unwrap();
+        assert_eq!(img.buffer().unwrap(), [0, 1, 2, 10, 11, 12, 20, 21, 22]);
+    }
+
+    #[test]
+    fn decodes_packed_palette_with_transparency() {
+        let palette = [255, 0, 0, 0, 0, 255];
+        let raw = [0, 0b1010_1010, 0b1100_0000];
+        let png = make_png(
+            10,
+            1,
+            1,
+            COLOR_TYPE_PALETTE,
+            0,
+            &[(b"PLTE", &palette), (b"tRNS", &[0])],
+            &raw,
+        );
+        let img = TGAImage::from_png_bytes(&png).unwrap();
+        assert_eq!(img.get_bytespp(), TGAFormat::RGBA as i32);
+        let red = img.get(1, 0);
+        assert_eq!([red[0], red[1], red[2], red[3]], [0, 0, 255, 0]);
+        let blue = img.get(8, 0);
+        assert_eq!([blue[0], blue[1], blue[2], blue[3]], [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn decodes_low_and_high_bit_depths() {
+        // 2-bit gray scales 0..3 to 0..255
+        let png = make_png(4, 1, 2, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 0b00_01_10_11]);
+        let img = TGAImage::from_png_bytes(&png).unwrap();
+        assert_eq!(img.buffer().unwrap(), [0, 85, 170, 255]);
+
+        // 16-bit RGB keeps the high bytes, with an up filter on the second row
+        let raw = [0, 0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 2, 1, 0, 1, 0, 1, 0];
+        let png = make_png(1, 2, 16, COLOR_TYPE_RGB, 0, &[], &raw);
+        let img = TGAImage::from_png_bytes(&png).unwrap();
+        assert_eq!(img.buffer().unwrap(), [0x9a, 0x56, 0x12, 0x9b, 0x57, 0x13]);
+
+        // 8-bit gray+alpha becomes RGBA
+        let png = make_png(1, 1, 8, COLOR_TYPE_GRAYSCALE_ALPHA, 0, &[], &[0, 40, 200]);
+        let img = TGAImage::from_png_bytes(&png).unwrap();
+        assert_eq!(img.buffer().unwrap(), [40, 40, 40, 200]);
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        let png = make_png(2, 2, 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 1, 2, 0, 3, 4]);
+        assert!(TGAImage::from_png_bytes(&png).is_ok());
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png[1..]),
+            Err(PngError::BadSignature)
+        ));
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png[..png.len() - 20]),
+            Err(PngError::Truncated { .. })
+        ));
+        let mut corrupt = png.clone();
+        corrupt[20] ^= 1;
+        assert!(matches!(
+            TGAImage::from_png_bytes(&corrupt),
+            Err(PngError::BadCrc { offset: 8 })
+        ));
+
+        let png = make_png(2, 2, 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[5, 1, 2, 0, 3, 4]);
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png),
+            Err(PngError::BadFilter { filter: 5 })
+        ));
+        let png = make_png(2, 2, 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0, 1, 2]);
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png),
+            Err(PngError::BadCompression)
+        ));
+        let png = make_png(1, 1, 4, COLOR_TYPE_RGB, 0, &[], &[0, 0]);
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png),
+            Err(PngError::UnsupportedFormat { .. })
+        ));
+        let png = make_png(0, 1, 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0]);
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png),
+            Err(PngError::BadDimensions { .. })
+        ));
+        let png = make_png(3, 2, 8, COLOR_TYPE_GRAYSCALE, 0, &[], &[0; 8]);
+        let limits = DecodeLimits {
+            max_width: 2,
+            max_height: 2,
+        };
+        assert!(matches!(
+            TGAImage::from_png_bytes_with_limits(&png, limits),
+            Err(PngError::TooLarge {
+                width: 3,
+                height: 2
+            })
+        ));
+        assert!(TGAImage::from_png_reader_with_limits(&png[..], DecodeLimits::default()).is_ok());
+        let png = make_png(1, 1, 8, COLOR_TYPE_PALETTE, 0, &[], &[0, 0]);
+        assert!(matches!(
+            TGAImage::from_png_bytes(&png),
+            Err(PngError::BadChunk { kind }) if &kind == b"PLTE"
+        ));
+    }
+}
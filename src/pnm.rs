@@ -0,0 +1,656 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Error, ErrorKind, Read, Write};
+
+use crate::float_image::FloatImage;
+use crate::tga::{DecodeLimits, TGAFormat, TGAImage};
+
+/*
+    Netpbm and PFM support
+
+    PGM/PPM (binary P5/P6 and ASCII P2/P3) and PAM (P7) for
+    TGAImage, PFM (PF/Pf) for FloatImage. Samples with a maxval
+    other than 255 are rescaled to 8 bits on load.
+*/
+
+/// Reasons a Netpbm or PFM file can fail to decode. Offsets are in bytes from the start of the file.
+#[derive(Debug)]
+pub enum PnmError {
+    Io(io::Error),
+    BadMagic,
+    /// A header field is missing or not a number.
+    BadHeader {
+        offset: u64,
+    },
+    BadDimensions {
+        width: u32,
+        height: u32,
+    },
+    /// The image exceeds the `DecodeLimits` it was decoded with.
+    TooLarge {
+        width: u32,
+        height: u32,
+    },
+    BadMaxval {
+        maxval: u32,
+    },
+    /// PAM depth or tuple type that doesn't map to gray, gray+alpha, RGB or RGBA.
+    BadDepth {
+        depth: u32,
+    },
+    Truncated {
+        offset: u64,
+    },
+    /// An ASCII sample is not a number or exceeds maxval.
+    BadSample {
+        offset: u64,
+    },
+}
+
+impl fmt::Display for PnmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PnmError::Io(e) => write!(f, "{e}"),
+            PnmError::BadMagic => write!(f, "not a PGM, PPM, PAM or PFM file"),
+            PnmError::BadHeader { offset } => write!(f, "malformed header at byte {offset}"),
+            PnmError::BadDimensions { width, height } => {
+                write!(f, "bad image dimensions {width}x{height}")
+            }
+            PnmError::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "image dimensions {width}x{height} exceed the decoding limits"
+                )
+            }
+            PnmError::BadMaxval { maxval } => write!(f, "bad maxval {maxval}"),
+            PnmError::BadDepth { depth } => write!(f, "unsupported PAM depth {depth}"),
+            PnmError::Truncated { offset } => write!(f, "data truncated at byte {offset}"),
+            PnmError::BadSample { offset } => write!(f, "bad sample at byte {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for PnmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PnmError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PnmError {
+    fn from(e: io::Error) -> Self {
+        PnmError::Io(e)
+    }
+}
+
+/// Splits a header into whitespace separated tokens, skipping `#` comments.
+struct Tokens<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a [u8]> {
+        loop {
+            match self.data.get(self.pos) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.pos), None | Some(b'\n') | Some(b'\r')) {
+                        self.pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.pos += 1,
+                Some(_) => break,
+                None => return None,
+            }
+        }
+        let start = self.pos;
+        while matches!(self.data.get(self.pos), Some(c) if !c.is_ascii_whitespace() && *c != b'#') {
+            self.pos += 1;
+        }
+        Some(&self.data[start..self.pos])
+    }
+
+    fn number(&mut self) -> Result<u32, PnmError> {
+        let offset = self.pos as u64;
+        self.next()
+            .and_then(|t| std::str::from_utf8(t).ok())
+            .and_then(|t| t.parse().ok())
+            .ok_or(PnmError::BadHeader { offset })
+    }
+
+    /// Skips the single whitespace byte that separates the header from binary data.
+    fn end_header(&mut self) -> Result<usize, PnmError> {
+        match self.data.get(self.pos) {
+            Some(c) if c.is_ascii_whitespace() => Ok(self.pos + 1),
+            _ => Err(PnmError::BadHeader {
+                offset: self.pos as u64,
+            }),
+        }
+    }
+}
+
+fn check_dimensions(width: u32, height: u32, limits: DecodeLimits) -> Result<(), PnmError> {
+    if width == 0 || height == 0 {
+        return Err(PnmError::BadDimensions { width, height });
+    }
+    if width > limits.max_width as u32 || height > limits.max_height as u32 {
+        return Err(PnmError::TooLarge { width, height });
+    }
+    Ok(())
+}
+
+/// Writes `samples` as ASCII numbers, keeping lines within the recommended 70 characters.
+fn write_ascii<W: Write>(out: &mut W, samples: impl Iterator<Item = u8>) -> io::Result<()> {
+    let mut line = 0;
+    for s in samples {
+        let text = s.to_string();
+        if line > 0 && line + 1 + text.len() > 70 {
+            out.write_all(b"\n")?;
+            line = 0;
+        } else if line > 0 {
+            out.write_all(b" ")?;
+            line += 1;
+        }
+        out.write_all(text.as_bytes())?;
+        line += text.len();
+    }
+    out.write_all(b"\n")
+}
+
+impl TGAImage {
+    pub fn read_pnm_file(&mut self, filename: &str) -> Result<(), PnmError> {
+        *self = TGAImage::from_pnm_reader(File::open(filename)?)?;
+        Ok(())
+    }
+
+    pub fn from_pnm_reader<R: Read>(reader: R) -> Result<TGAImage, PnmError> {
+        TGAImage::from_pnm_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    pub fn from_pnm_reader_with_limits<R: Read>(
+        mut reader: R,
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, PnmError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        TGAImage::from_pnm_bytes_with_limits(&bytes, limits)
+    }
+
+    /// Decodes a P2, P3, P5, P6 or P7 file. Gray+alpha PAMs become RGBA images.
+    pub fn from_pnm_bytes(bytes: &[u8]) -> Result<TGAImage, PnmError> {
+        TGAImage::from_pnm_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    pub fn from_pnm_bytes_with_limits(
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, PnmError> {
+        let magic = bytes.get(..2).ok_or(PnmError::BadMagic)?;
+        let mut tokens = Tokens {
+            data: bytes,
+            pos: 2,
+        };
+        let (ascii, width, height, depth, maxval) = match magic {
+            b"P2" | b"P3" | b"P5" | b"P6" => {
+                let width = tokens.number()?;
+                let height = tokens.number()?;
+                let maxval = tokens.number()?;
+                let depth = if magic[1] == b'2' || magic[1] == b'5' {
+                    1
+                } else {
+                    3
+                };
+                (magic[1] <= b'3', width, height, depth, maxval)
+            }
+            b"P7" => {
+                let (mut width, mut height, mut depth, mut maxval) = (0, 0, 0, 0);
+                let mut tupltype = Vec::new();
+                loop {
+                    let offset = tokens.pos as u64;
+                    match tokens.next().ok_or(PnmError::BadHeader { offset })? {
+                        b"WIDTH" => width = tokens.number()?,
+                        b"HEIGHT" => height = tokens.number()?,
+                        b"DEPTH" => depth = tokens.number()?,
+                        b"MAXVAL" => maxval = tokens.number()?,
+                        b"TUPLTYPE" => {
+                            tupltype = tokens.next().unwrap_or_default().to_vec();
+                        }
+                        b"ENDHDR" => break,
+                        _ => return Err(PnmError::BadHeader { offset }),
+                    }
+                }
+                let expected = match &tupltype[..] {
+                    b"" => depth,
+                    b"GRAYSCALE" | b"BLACKANDWHITE" => 1,
+                    b"GRAYSCALE_ALPHA" | b"BLACKANDWHITE_ALPHA" => 2,
+                    b"RGB" => 3,
+                    b"RGB_ALPHA" => 4,
+                    _ => 0,
+                };
+                if !(1..=4).contains(&depth) || depth != expected {
+                    return Err(PnmError::BadDepth { depth });
+                }
+                (false, width, height, depth, maxval)
+            }
+            _ => return Err(PnmError::BadMagic),
+        };
+        check_dimensions(width, height, limits)?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(PnmError::BadMaxval { maxval });
+        }
+
+        let count = width as usize * height as usize * depth as usize;
+        let mut samples = Vec::with_capacity(count.min(bytes.len()));
+        if ascii {
+            for _ in 0..count {
+                let offset = tokens.pos as u64;
+                let v = match tokens.next() {
+                    Some(t) => std::str::from_utf8(t)
+                        .ok()
+                        .and_then(|t| t.parse::<u32>().ok())
+                        .filter(|&v| v <= maxval)
+                        .ok_or(PnmError::BadSample { offset })?,
+                    None => return Err(PnmError::Truncated { offset }),
+                };
+                samples.push(v);
+            }
+        } else {
+            let start = tokens.end_header()?;
+            let size = if maxval < 256 { 1 } else { 2 };
+            let data = bytes
+                .get(start..start + count * size)
+                .ok_or(PnmError::Truncated {
+                    offset: bytes.len() as u64,
+                })?;
+            if size == 1 {
+                samples.extend(data.iter().map(|&v| v as u32));
+            } else {
+                samples.extend(
+                    data.chunks(2)
+                        .map(|v| u16::from_be_bytes([v[0], v[1]]) as u32),
+                );
+            }
+            if samples.iter().any(|&v| v > maxval) {
+                return Err(PnmError::BadSample {
+                    offset: start as u64,
+                });
+            }
+        }
+
+        let bytespp = match depth {
+            1 => TGAFormat::Grayscale,
+            3 => TGAFormat::RGB,
+            _ => TGAFormat::RGBA,
+        } as usize;
+        let mut img = TGAImage::new_dimensions(width as i32, height as i32, bytespp as i32);
+        let out = img.buffer_mut().unwrap();
+        let scale = |v: u32| ((v * 255 + maxval / 2) / maxval) as u8;
+        for (px, s) in out.chunks_mut(bytespp).zip(samples.chunks(depth as usize)) {
+            match depth {
+                1 => px[0] = scale(s[0]),
+                2 => px.copy_from_slice(&[scale(s[0]), scale(s[0]), scale(s[0]), scale(s[1])]),
+                _ => {
+                    px[0] = scale(s[2]);
+                    px[1] = scale(s[1]);
+                    px[2] = scale(s[0]);
+                    if depth == 4 {
+                        px[3] = scale(s[3]);
+                    }
+                }
+            }
+        }
+        Ok(img)
+    }
+
+    /// Writes a PGM for grayscale images and a PPM otherwise, dropping any alpha channel.
+    pub fn write_pnm_file(&self, filename: &str, ascii: bool) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_pnm_to(&mut out, ascii)?;
+        out.flush()
+    }
+
+    pub fn write_pnm_to<W: Write>(&self, mut out: W, ascii: bool) -> io::Result<()> {
+        let data = self
+            .buffer()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "image has no data"))?;
+        let bpp = self.get_bytespp() as usize;
+        let gray = bpp == TGAFormat::Grayscale as usize;
+        let magic = match (gray, ascii) {
+            (true, true) => "P2",
+            (false, true) => "P3",
+            (true, false) => "P5",
+            (false, false) => "P6",
+        };
+        write!(
+            out,
+            "{}\n{} {}\n255\n",
+            magic,
+            self.get_width(),
+            self.get_height()
+        )?;
+        let mut samples = Vec::with_capacity(data.len());
+        for px in data.chunks(bpp) {
+            if gray {
+                samples.push(px[0]);
+            } else {
+                samples.extend_from_slice(&[px[2], px[1], px[0]]);
+            }
+        }
+        if ascii {
+            write_ascii(&mut out, samples.into_iter())
+        } else {
+            out.write_all(&samples)
+        }
+    }
+
+    /// Writes a binary PAM, which unlike PPM keeps the alpha channel.
+    pub fn write_pam_file(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_pam_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn write_pam_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let data = self
+            .buffer()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "image has no data"))?;
+        let bpp = self.get_bytespp() as usize;
+        let tupltype = match bpp {
+            1 => "GRAYSCALE",
+            3 => "RGB",
+            4 => "RGB_ALPHA",
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "PAM needs a grayscale, RGB or RGBA image",
+                ))
+            }
+        };
+        write!(
+            out,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL 255\nTUPLTYPE {}\nENDHDR\n",
+            self.get_width(),
+            self.get_height(),
+            bpp,
+            tupltype
+        )?;
+        let mut row = Vec::with_capacity(data.len());
+        for px in data.chunks(bpp) {
+            match bpp {
+                1 => row.push(px[0]),
+                _ => {
+                    row.extend_from_slice(&[px[2], px[1], px[0]]);
+                    row.extend_from_slice(&px[3..]);
+                }
+            }
+        }
+        out.write_all(&row)
+    }
+}
+
+impl FloatImage {
+    pub fn read_pfm_file(&mut self, filename: &str) -> Result<(), PnmError> {
+        *self = FloatImage::from_pfm_reader(File::open(filename)?)?;
+        Ok(())
+    }
+
+    pub fn from_pfm_reader<R: Read>(reader: R) -> Result<FloatImage, PnmError> {
+        FloatImage::from_pfm_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    pub fn from_pfm_reader_with_limits<R: Read>(
+        mut reader: R,
+        limits: DecodeLimits,
+    ) -> Result<FloatImage, PnmError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        FloatImage::from_pfm_bytes_with_limits(&bytes, limits)
+    }
+
+    /// Decodes a grayscale (Pf) or RGB (PF) PFM. The scale magnitude is ignored.
+    pub fn from_pfm_bytes(bytes: &[u8]) -> Result<FloatImage, PnmError> {
+        FloatImage::from_pfm_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    pub fn from_pfm_bytes_with_limits(
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<FloatImage, PnmError> {
+        let channels = match bytes.get(..2) {
+            Some(b"Pf") => 1,
+            Some(b"PF") => 3,
+            _ => return Err(PnmError::BadMagic),
+        };
+        let mut tokens = Tokens {
+            data: bytes,
+            pos: 2,
+        };
+        let width = tokens.number()?;
+        let height = tokens.number()?;
+        let offset = tokens.pos as u64;
+        let scale: f32 = tokens
+            .next()
+            .and_then(|t| std::str::from_utf8(t).ok())
+            .and_then(|t| t.parse().ok())
+            .filter(|s: &f32| *s != 0. && s.is_finite())
+            .ok_or(PnmError::BadHeader { offset })?;
+        check_dimensions(width, height, limits)?;
+        let start = tokens.end_header()?;
+
+        let (w, h) = (width as usize, height as usize);
+        let line = w * channels;
+        let data = bytes
+            .get(start..start + line * h * 4)
+            .ok_or(PnmError::Truncated {
+                offset: bytes.len() as u64,
+            })?;
+        let mut samples = Vec::with_capacity(line * h);
+        // rows are stored from the bottom
+        for y in (0..h).rev() {
+            for v in data[y * line * 4..(y + 1) * line * 4].chunks(4) {
+                let v = [v[0], v[1], v[2], v[3]];
+                samples.push(if scale < 0. {
+                    f32::from_le_bytes(v)
+                } else {
+                    f32::from_be_bytes(v)
+                });
+            }
+        }
+        Ok(FloatImage::from_data(width as i32, height as i32, channels as i32, samples).unwrap())
+    }
+
+    /// Writes a little-endian PFM. Images with three or more channels are saved as RGB.
+    pub fn write_pfm_file(&self, filename: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_pfm_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn write_pfm_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (magic, keep) = match self.get_channels() {
+            1 => ("Pf", 1),
+            2 => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "PFM needs one or at least three channels",
+                ))
+            }
+            _ => ("PF", 3),
+        };
+        write!(
+            out,
+            "{}\n{} {}\n-1.0\n",
+            magic,
+            self.get_width(),
+            self.get_height()
+        )?;
+        let channels = self.get_channels() as usize;
+        let line = self.get_width() as usize * channels;
+        let mut bytes = Vec::with_capacity(self.buffer().len() * 4);
+        for row in self.buffer().chunks(line.max(1)).rev() {
+            for px in row.chunks(channels) {
+                for v in &px[..keep] {
+                    bytes.extend_from_slice(&v.to_le_bytes());
+                }
+            }
+        }
+        out.write_all(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::TGAColor;
+
+    fn test_image(bpp: i32) -> TGAImage {
+        let mut img = TGAImage::new_dimensions(13, 7, bpp);
+        for y in 0..7 {
+            for x in 0..13 {
+                let v = (x * 19 + y * 31) as u8;
+                img.set(x, y, &TGAColor::new_rgba(v, v ^ 0x55, 255 - v, v / 2));
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn pnm_round_trip() {
+        for &bpp in &[1, 3] {
+            let img = test_image(bpp);
+            for &ascii in &[false, true] {
+                let mut bytes = Vec::new();
+                img.write_pnm_to(&mut bytes, ascii).unwrap();
+                let magic = match (bpp, ascii) {
+                    (1, false) => b"P5",
+                    (1, true) => b"P2",
+                    (_, false) => b"P6",
+                    _ => b"P3",
+                };
+                assert_eq!(&bytes[..2], magic);
+                if ascii {
+                    assert!(bytes.split(|&b| b == b'\n').all(|l| l.len() <= 70));
+                }
+                let read = TGAImage::from_pnm_bytes(&bytes).unwrap();
+                assert_eq!(read.buffer(), img.buffer());
+            }
+        }
+    }
+
+    #[test]
+    fn pam_keeps_alpha() {
+        let img = test_image(4);
+        let mut bytes = Vec::new();
+        img.write_pam_to(&mut bytes).unwrap();
+        let read = TGAImage::from_pnm_bytes(&bytes).unwrap();
+        assert_eq!(read.get_bytespp(), 4);
+        assert_eq!(read.buffer(), img.buffer());
+
+        let gray_alpha = b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\x40\x80";
+        let read = TGAImage::from_pnm_bytes(gray_alpha).unwrap();
+        assert_eq!(read.buffer().unwrap(), [0x40, 0x40, 0x40, 0x80]);
+    }
+
+    #[test]
+    fn rescales_maxval_and_skips_comments() {
+        let ascii = b"P3 # comment\n# another\n2 1\n# before maxval\n15\n15 0 5  0 15 0\n";
+        let img = TGAImage::from_pnm_bytes(ascii).unwrap();
+        assert_eq!(img.buffer().unwrap(), [85, 0, 255, 0, 255, 0]);
+
+        let wide = b"P5\n2 1\n65535\n\xff\xff\x80\x00";
+        let img = TGAImage::from_pnm_bytes(wide).unwrap();
+        assert_eq!(img.buffer().unwrap(), [255, 128]);
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let depth = FloatImage::from_data(3, 2, 1, vec![0., 0.5, 1., -2., f32::MAX, 1e-7]).unwrap();
+        let mut bytes = Vec::new();
+        depth.write_pfm_to(&mut bytes).unwrap();
+        assert_eq!(&bytes[..14], b"Pf\n3 2\n-1.0\n\0\0");
+        // bottom row first
+        assert_eq!(bytes[12..16], (-2f32).to_le_bytes());
+        assert_eq!(FloatImage::from_pfm_bytes(&bytes).unwrap(), depth);
+
+        let rgba = FloatImage::from_data(1, 1, 4, vec![1., 2., 3., 4.]).unwrap();
+        let mut bytes = Vec::new();
+        rgba.write_pfm_to(&mut bytes).unwrap();
+        let rgb = FloatImage::from_pfm_bytes(&bytes).unwrap();
+        assert_eq!(rgb.buffer(), [1., 2., 3.]);
+
+        let mut big_endian = b"Pf\n1 1\n1.0\n".to_vec();
+        big_endian.extend_from_slice(&0.25f32.to_be_bytes());
+        assert_eq!(
+            FloatImage::from_pfm_bytes(&big_endian).unwrap().buffer(),
+            [0.25]
+        );
+    }
+
+    #[test]
+    fn float_image_converts_to_and_from_tga() {
+        let img = test_image(4);
+        let float = FloatImage::from_tga(&img);
+        let c = img.get(3, 2);
+        assert_eq!(float.get(3, 2).unwrap()[0], c[2] as f32 / 255.);
+        assert_eq!(float.to_tga().buffer(), img.buffer());
+    }
+
+    #[test]
+    fn rejects_broken_files() {
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P9\n1 1\n255\n\0"),
+            Err(PnmError::BadMagic)
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P5\n1 x\n255\n\0"),
+            Err(PnmError::BadHeader { offset: 4 })
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P5\n0 1\n255\n"),
+            Err(PnmError::BadDimensions { .. })
+        ));
+        let limits = DecodeLimits {
+            max_width: 2,
+            max_height: 2,
+        };
+        assert!(matches!(
+            TGAImage::from_pnm_bytes_with_limits(b"P5\n3 1\n255\n\0\0\0", limits),
+            Err(PnmError::TooLarge {
+                width: 3,
+                height: 1
+            })
+        ));
+        assert!(TGAImage::from_pnm_reader_with_limits(&b"P5\n2 1\n255\n\0\0"[..], limits).is_ok());
+        assert!(matches!(
+            FloatImage::from_pfm_bytes_with_limits(b"Pf\n1 3\n-1.0\n", limits),
+            Err(PnmError::TooLarge {
+                width: 1,
+                height: 3
+            })
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P5\n1 1\n70000\n\0"),
+            Err(PnmError::BadMaxval { maxval: 70000 })
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P6\n2 2\n255\n\0\0\0"),
+            Err(PnmError::Truncated { .. })
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(b"P2\n2 1\n7\n3 8\n"),
+            Err(PnmError::BadSample { .. })
+        ));
+        assert!(matches!(
+            TGAImage::from_pnm_bytes(
+                b"P7\nWIDTH 1\nHEIGHT 1\nDEPTH 3\nMAXVAL 255\nTUPLTYPE GRAYSCALE\nENDHDR\n"
+            ),
+            Err(PnmError::BadDepth { depth: 3 })
+        ));
+        assert!(matches!(
+            FloatImage::from_pfm_bytes(b"PF\n1 1\n-1.0\n\0\0\0\0"),
+            Err(PnmError::Truncated { .. })
+        ));
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    float_image::FloatImage, geometry::*, model::Model, our_gl::*, sampler::Sampler, shader::Shader,
+};
+
+/*
+    Rendering a model
+
+    Puts the pieces together the way the tinyrenderer demo
+    does: a camera looking at the model, the normal mapped
+    Phong shader, a float RGBA color buffer and a z-buffer.
+    Callers pick what to do with the frame: tone map it,
+    save it or composite it.
+
+    The z-buffer is cleared to 1, the far plane.
+*/
+
+/// Where the model is looked at from.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub eye: Vec3f,
+    pub center: Vec3f,
+    pub up: Vec3f,
+    /// Vertical field of view, in degrees.
+    pub fov_y: f32,
+    /// Distances from the eye of the near and far planes.
+    pub near: f32,
+    pub far: f32,
+    /// Parallel projection, showing at any depth what the perspective one shows at `center`.
+    pub orthographic: bool,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        let eye = Vec3f::new_args(1., 1., 3.);
+        let center = Vec3f::new_args(0., 0., 0.);
+        Camera {
+            eye,
+            center,
+            up: Vec3f::new_args(0., 1., 0.),
+            // the field of view tinyrenderer's projection has from this eye
+            fov_y: 2. * (1. / (eye - center).norm()).atan().to_degrees(),
+            near: 0.1,
+            far: 100.,
+            orthographic: false,
+        }
+    }
+}
+
+impl Camera {
+    /// Looks at the center of the box from the current viewing direction, just far enough for
+    /// the box's bounding sphere to fit the field of view of a viewport `aspect` times as wide as
+    /// it is high, with near and far hugging the sphere.
+    pub fn frame_box(&mut self, min: Vec3f, max: Vec3f, aspect: f32) {
+        let center = (min + max) * 0.5;
+        let radius = ((max - min) * 0.5).norm().max(f32::EPSILON);
+        let half_y = self.fov_y.to_radians() / 2.;
+        let half_x = (half_y.tan() * aspect).atan();
+        let distance = radius / half_x.min(half_y).sin();
+        let dir = (self.eye - self.center).normalize().to_owned();
+        self.eye = center + dir * distance;
+        self.center = center;
+        self.near = (distance - radius).max(distance * 1e-3);
+        self.far = distance + radius;
+    }
+
+    /// Projection matrix for a viewport `aspect` times as wide as it is high.
+    pub fn projection(&self, aspect: f32) -> Matrix {
+        let fov_y = self.fov_y.to_radians();
+        if self.orthographic {
+            let t = (self.eye - self.center).norm() * (fov_y / 2.).tan();
+            orthographic(-t * aspect, t * aspect, -t, t, self.near, self.far)
+        } else {
+            perspective(fov_y, aspect, self.near, self.far)
+        }
+    }
+}
+
+/// Largest image width or height `render` accepts, keeping pixel indices of the RGBA target
+/// within `i32`.
+pub const MAX_IMAGE_SIZE: i32 = 16384;
+
+/// Everything `render` needs besides the model.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    /// Image width, from 1 to `MAX_IMAGE_SIZE`.
+    pub width: i32,
+    /// Image height, from 1 to `MAX_IMAGE_SIZE`.
+    pub height: i32,
+    /// Fraction of the width and height left empty on each side of the viewport.
+    pub viewport_margin: f32,
+    pub camera: Camera,
+    /// Direction towards the light, in world space.
+    pub light_dir: Vec3f,
+    pub sampler: Sampler,
+    pub state: RenderState,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            width: 800,
+            height: 800,
+            viewport_margin: 0.125,
+            camera: Camera::default(),
+            light_dir: Vec3f::new_args(1., 1., 1.),
+            sampler: Sampler::default(),
+            state: RenderState::default(),
+        }
+    }
+}
+
+/// How many of the model's triangles `render` drew, and why it skipped the others.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RenderStats {
+    pub drawn: usize,
+    pub clipped: usize,
+    pub culled: usize,
+}
+
+/// The result of `render`. Both images have their top row first, like TGAImage files on disk.
+pub struct Frame {
+    /// Linear RGBA color, unclamped; pixels no triangle covered are transparent black.
+    pub color: FloatImage,
+    /// Normalized depth of the nearest fragment per pixel, 0 on the near plane and 1 on the far
+    /// one, where nothing was drawn.
+    pub depth: FloatImage,
+    pub stats: RenderStats,
+}
+
+impl RenderOptions {
+    /// Position and size of the viewport inside the image, left after the margins.
+    fn viewport_rect(&self) -> (i32, i32, i32, i32) {
+        let (w, h) = (self.width, self.height);
+        let mx = (w as f32 * self.viewport_margin) as i32;
+        let my = (h as f32 * self.viewport_margin) as i32;
+        (mx, my, w - 2 * mx, h - 2 * my)
+    }
+
+    /// Width over height of the viewport.
+    pub fn aspect(&self) -> f32 {
+        let (_, _, vw, vh) = self.viewport_rect();
+        vw as f32 / vh as f32
+    }
+
+    /// Model, viewport and projection matrices for these options.
+    pub fn view_bundle(&self) -> ViewBundle {
+        let Camera {
+            eye, center, up, ..
+        } = self.camera;
+        let (mx, my, vw, vh) = self.viewport_rect();
+        ViewBundle {
+            ModelView: lookat(eye, center, up),
+            ViewPort: viewport(mx, my, vw, vh),
+            Projection: self.camera.projection(self.aspect()),
+        }
+    }
+}
+
+/// Renders every triangle of `model` with the normal mapped shader. Panics if the image size is
+/// out of range.
+pub fn render(model: &Model, options: &RenderOptions) -> Frame {
+    let (width, height) = (options.width, options.height);
+    assert!(
+        (1..=MAX_IMAGE_SIZE).contains(&width) && (1..=MAX_IMAGE_SIZE).contains(&height),
+        "image size {}x{} out of range",
+        width,
+        height
+    );
+    let view_bundle = options.view_bundle();
+    let mut color = FloatImage::new_dimensions(width, height, 4);
+    let mut zbuffer = vec![1.; width as usize * height as usize];
+
+    let mut shader = Shader::new(model, &view_bundle, options.light_dir, options.sampler);
+    let mut stats = RenderStats::default();
+    for i in 0..model.nfaces() {
+        let mut clip_vert = [Vec4f::new(); 3];
+        for j in 0..3 {
+            clip_vert[j as usize] = shader.vertex(i as i32, j, &view_bundle);
+        }
+        match triangle(
+            &clip_vert,
+            &shader,
+            &mut color,
+            &mut zbuffer,
+            &view_bundle,
+            &options.state,
+        ) {
+            TriangleOutcome::Drawn => stats.drawn += 1,
+            TriangleOutcome::Clipped => stats.clipped += 1,
+            TriangleOutcome::Culled => stats.culled += 1,
+        }
+    }
+
+    // rasterization has y pointing up
+    color.flip_vertically();
+    let mut depth = FloatImage::from_data(width, height, 1, zbuffer).unwrap();
+    depth.flip_vertically();
+    Frame {
+        color,
+        depth,
+        stats,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::MipFilter;
+
+    #[test]
+    fn renders_the_floor() {
+        let model = Model::new_args("obj/floor.obj", MipFilter::Box);
+        let options = RenderOptions {
+            width: 64,
+            height: 48,
+            ..RenderOptions::default()
+        };
+        let frame = render(&model, &options);
+        assert_eq!(
+            (frame.color.get_width(), frame.color.get_height()),
+            (64, 48)
+        );
+        assert_eq!(
+            (frame.depth.get_width(), frame.depth.get_height()),
+            (64, 48)
+        );
+
+        // the floor is below the camera, so it covers the bottom of the image but not the top
+        let bottom = frame.color.get(32, 44).unwrap();
+        assert!(bottom[0] > 0. && bottom[3] == 1.);
+        let depth = frame.depth.get(32, 44).unwrap()[0];
+        assert!(depth > 0. && depth < 1.);
+        assert_eq!(frame.color.get(32, 2).unwrap(), [0.; 4]);
+        assert_eq!(frame.depth.get(32, 2).unwrap(), [1.]);
+        assert_eq!(frame.stats.drawn, model.nfaces());
+    }
+
+    #[test]
+    fn culls_faces_turned_away() {
+        let model = Model::new_args("obj/floor.obj", MipFilter::Box);
+        let mut options = RenderOptions {
+            width: 32,
+            height: 32,
+            ..RenderOptions::default()
+        };
+        // from below, the floor shows its back
+        options.camera.eye = Vec3f::new_args(1., -3., 3.);
+        let frame = render(&model, &options);
+        assert_eq!(
+            frame.stats,
+            RenderStats {
+                drawn: 0,
+                clipped: 0,
+                culled: 2
+            }
+        );
+        assert!(frame.color.buffer().iter().all(|&c| c == 0.));
+
+        options.state.cull_mode = CullMode::None;
+        let frame = render(&model, &options);
+        assert_eq!(frame.stats.drawn, 2);
+        assert_eq!(frame.color.get(16, 16).unwrap()[3], 1.);
+
+        options.state.cull_mode = CullMode::Back;
+        options.state.front_face = FrontFace::Cw;
+        assert_eq!(render(&model, &options).stats.drawn, 2);
+    }
+
+    #[test]
+    fn frames_bounding_boxes() {
+        let mut camera = Camera {
+            fov_y: 60.,
+            ..Camera::default()
+        };
+        camera.frame_box(Vec3f::new_args(1., 1., 1.), Vec3f::new_args(3., 3., 3.), 1.);
+        let center = Vec3f::new_args(2., 2., 2.);
+        // the bounding sphere has radius sqrt(3) and sin(30 degrees) is 1/2
+        let distance = (camera.eye - center).norm();
+        assert!((distance - 2. * 3f32.sqrt()).abs() < 1e-4);
+        assert!((camera.near - 3f32.sqrt()).abs() < 1e-4);
+        assert!((camera.far - 3. * 3f32.sqrt()).abs() < 1e-4);
+        assert_eq!(camera.center.x, 2.);
+        // still looking along the original direction
+        let dir = (camera.eye - center).normalize().to_owned();
+        let expected = Vec3f::new_args(1., 1., 3.).normalize().to_owned();
+        assert!((dir * expected - 1.).abs() < 1e-5);
+
+        // a wide viewport is limited by the vertical field of view, a tall one by the narrower
+        // horizontal one
+        let mut wide = Camera {
+            fov_y: 60.,
+            ..Camera::default()
+        };
+        wide.frame_box(Vec3f::new_args(1., 1., 1.), Vec3f::new_args(3., 3., 3.), 2.);
+        assert!(((wide.eye - center).norm() - distance).abs() < 1e-4);
+        let mut tall = Camera {
+            fov_y: 60.,
+            ..Camera::default()
+        };
+        tall.frame_box(
+            Vec3f::new_args(1., 1., 1.),
+            Vec3f::new_args(3., 3., 3.),
+            0.5,
+        );
+        let half_x = (30f32.to_radians().tan() * 0.5).atan();
+        let expected = 3f32.sqrt() / half_x.sin();
+        assert!(((tall.eye - center).norm() - expected).abs() < 1e-3);
+
+        // near and far of a framed model hug it
+        let model = Model::new_args("obj/floor.obj", crate::texture::MipFilter::Box);
+        let (min, max) = model.bounding_box();
+        assert_eq!(
+            [min.x, min.y, min.z, max.x, max.y, max.z],
+            [-1., -1., -1., 1., -1., 1.]
+        );
+        let mut options = RenderOptions {
+            width: 32,
+            height: 32,
+            ..RenderOptions::default()
+        };
+        options.camera.frame_box(min, max, options.aspect());
+        let frame = render(&model, &options);
+        assert!(frame
+            .depth
+            .buffer()
+            .iter()
+            .all(|&d| (0. ..=1.).contains(&d)));
+        assert!(frame.depth.buffer().iter().any(|&d| d < 0.5));
+        assert!(frame.color.get(16, 16).unwrap()[3] == 1.);
+
+        // a parallel projection of the same shows the floor as a parallelogram of the same
+        // height at the front and back
+        options.camera.orthographic = true;
+        let frame = render(&model, &options);
+        let covered = |y| {
+            (0..32)
+                .filter(|&x| frame.color.get(x, y).unwrap()[3] == 1.)
+                .count()
+        };
+        let rows: Vec<i32> = (0..32).filter(|&y| covered(y) > 0).collect();
+        let (first, last) = (rows[0], *rows.last().unwrap());
+        assert!((covered(first + 1) as i32 - covered(last - 1) as i32).abs() <= 2);
+
+        // framed for a portrait image, the floor stays clear of the sides
+        let mut options = RenderOptions {
+            width: 16,
+            height: 48,
+            viewport_margin: 0.,
+            ..RenderOptions::default()
+        };
+        options.camera.frame_box(min, max, options.aspect());
+        let frame = render(&model, &options);
+        let side = |x| (0..48).any(|y| frame.color.get(x, y).unwrap()[3] == 1.);
+        assert!(side(4) && side(11));
+        assert!(!side(0) && !side(15));
+    }
+}
@@ -0,0 +1,463 @@
+use std::sync::OnceLock;
+
+use crate::geometry::{Vec2f, Vec4f};
+use crate::hdr::srgb_decode;
+use crate::texture::{ColorSpace, Texture};
+use crate::tga::TGAImage;
+
+/*
+    Texture sampler
+
+    Looks up a TGAImage at normalized UV coordinates, with
+    nearest or bilinear filtering and a wrap mode per axis.
+    Texel centers sit at (i + 0.5) / size, as in OpenGL.
+    Textures with mip levels are sampled at a level of detail
+    derived from screen-space UV derivatives; bilinear plus
+    linear mipmap blending is trilinear filtering. Anisotropic
+    filtering takes several trilinear samples along the long
+    axis of the pixel footprint, at the finer level of detail
+    of its short axis, as in EXT_texture_filter_anisotropic.
+    Texels of sRGB textures are decoded to linear before they
+    are filtered, as GPUs do for sRGB formats.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+/// How the mip level is picked from the level of detail.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MipmapMode {
+    /// Always sample the full resolution level.
+    None,
+    /// Use the closest level.
+    Nearest,
+    /// Blend the two closest levels.
+    Linear,
+}
+
+/// How coordinates outside [0, 1] are brought back into the texture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps texel index `i` into 0..n.
+    pub fn apply(self, i: i32, n: i32) -> i32 {
+        match self {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m >= n {
+                    2 * n - 1 - m
+                } else {
+                    m
+                }
+            }
+        }
+    }
+}
+
+/// The largest `max_anisotropy` honored, as in Direct3D and most OpenGL implementations.
+pub const MAX_ANISOTROPY: f32 = 16.;
+
+/// `max_anisotropy` as used: within [1, `MAX_ANISOTROPY`], NaN counting as 1.
+fn clamp_anisotropy(max_anisotropy: f32) -> f32 {
+    if max_anisotropy.is_nan() {
+        1.
+    } else {
+        max_anisotropy.clamp(1., MAX_ANISOTROPY)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub mipmap: MipmapMode,
+    /// Most samples taken along the footprint for anisotropic filtering; 1 turns it off. Used
+    /// clamped to [1, `MAX_ANISOTROPY`], with NaN taken as 1.
+    pub max_anisotropy: f32,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler::new(Filter::Bilinear, Wrap::Repeat)
+    }
+}
+
+impl Sampler {
+    pub fn new(filter: Filter, wrap: Wrap) -> Self {
+        Sampler {
+            filter,
+            mipmap: MipmapMode::None,
+            max_anisotropy: 1.,
+            wrap_u: wrap,
+            wrap_v: wrap,
+        }
+    }
+
+    /// Texel (x, y) after wrapping, as RGBA in [0, 1]. Grayscale is replicated to RGB and
+    /// textures without alpha are opaque. sRGB color channels are decoded to linear.
+    pub fn texel(&self, img: &TGAImage, x: i32, y: i32, colorspace: ColorSpace) -> Vec4f {
+        let c = img.get(
+            self.wrap_u.apply(x, img.get_width()),
+            self.wrap_v.apply(y, img.get_height()),
+        );
+        let v = |i: usize| match colorspace {
+            ColorSpace::Srgb => srgb_to_linear(c[i]),
+            ColorSpace::Linear => c[i] as f32 / 255.,
+        };
+        let a = |i: usize| c[i] as f32 / 255.;
+        match img.get_bytespp() {
+            1 => Vec4f::new_args(v(0), v(0), v(0), 1.),
+            4 => Vec4f::new_args(v(2), v(1), v(0), a(3)),
+            _ => Vec4f::new_args(v(2), v(1), v(0), 1.),
+        }
+    }
+
+    /// Bilinear filtering with linear blending between mip levels.
+    pub fn trilinear(wrap: Wrap) -> Self {
+        Sampler {
+            mipmap: MipmapMode::Linear,
+            ..Sampler::new(Filter::Bilinear, wrap)
+        }
+    }
+
+    /// Trilinear filtering plus up to `max_anisotropy` samples along the pixel footprint, clamped
+    /// to [1, `MAX_ANISOTROPY`].
+    pub fn anisotropic(wrap: Wrap, max_anisotropy: f32) -> Self {
+        Sampler {
+            max_anisotropy: clamp_anisotropy(max_anisotropy),
+            ..Sampler::trilinear(wrap)
+        }
+    }
+
+    /// Lengths in base level texels of the longer and shorter footprint axes, and the longer
+    /// axis in UV units.
+    fn footprint(tex: &Texture, duv_dx: &Vec2f, duv_dy: &Vec2f) -> (f32, f32, Vec2f) {
+        let (w, h) = (
+            tex.base().get_width() as f32,
+            tex.base().get_height() as f32,
+        );
+        let len = |d: &Vec2f| ((d.x * w).powi(2) + (d.y * h).powi(2)).sqrt();
+        let (lx, ly) = (len(duv_dx), len(duv_dy));
+        if lx >= ly {
+            (lx, ly, *duv_dx)
+        } else {
+            (ly, lx, *duv_dy)
+        }
+    }
+
+    /// Level of detail for a pixel footprint with the given UV derivatives: log2 of the
+    /// longer footprint axis in base level texels.
+    pub fn lod(&self, tex: &Texture, duv_dx: &Vec2f, duv_dy: &Vec2f) -> f32 {
+        Sampler::footprint(tex, duv_dx, duv_dy).0.log2()
+    }
+
+    /// Samples `tex` at level of detail `lod`, 0 being the base level.
+    pub fn sample_lod(&self, tex: &Texture, uv: &Vec2f, lod: f32) -> Vec4f {
+        let lod = lod.clamp(0., (tex.levels() - 1) as f32);
+        match self.mipmap {
+            MipmapMode::None => self.sample_in(tex.base(), uv, tex.colorspace()),
+            MipmapMode::Nearest => {
+                self.sample_in(tex.level(lod.round() as usize), uv, tex.colorspace())
+            }
+            MipmapMode::Linear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let a = self.sample_in(tex.level(level as usize), uv, tex.colorspace());
+                if t == 0. {
+                    return a;
+                }
+                let b = self.sample_in(tex.level(level as usize + 1), uv, tex.colorspace());
+                Vec4f::new_args(
+                    a.x + (b.x - a.x) * t,
+                    a.y + (b.y - a.y) * t,
+                    a.z + (b.z - a.z) * t,
+                    a.a + (b.a - a.a) * t,
+                )
+            }
+        }
+    }
+
+    /// Samples `tex` at the level of detail implied by the screen-space UV derivatives, filtering
+    /// anisotropically if `max_anisotropy` is above 1.
+    pub fn sample_grad(&self, tex: &Texture, uv: &Vec2f, duv_dx: &Vec2f, duv_dy: &Vec2f) -> Vec4f {
+        let (pmax, pmin, major) = Sampler::footprint(tex, duv_dx, duv_dy);
+        // the field is public, so it may not have gone through `anisotropic`
+        let max_anisotropy = clamp_anisotropy(self.max_anisotropy);
+        if max_anisotropy <= 1. || pmax <= pmin {
+            return self.sample_lod(tex, uv, pmax.log2());
+        }
+        let ratio = (pmax / pmin).min(max_anisotropy);
+        let n = ratio.ceil() as usize;
+        let lod = (pmax / ratio).log2();
+        let mut sum = Vec4f::new();
+        for i in 0..n {
+            // spread the samples evenly over the long axis, centered on the pixel
+            let t = (i as f32 + 0.5) / n as f32 - 0.5;
+            let c = self.sample_lod(tex, &(*uv + major * t), lod);
+            sum = Vec4f::new_args(sum.x + c.x, sum.y + c.y, sum.z + c.z, sum.a + c.a);
+        }
+        sum / n as f32
+    }
+
+    /// Filtered color at `uv`, as RGBA in [0, 1], with the texel bytes taken as linear values.
+    /// Images without data sample as transparent black.
+    pub fn sample(&self, img: &TGAImage, uv: &Vec2f) -> Vec4f {
+        self.sample_in(img, uv, ColorSpace::Linear)
+    }
+
+    /// Filtered linear color at `uv` of an image stored in `colorspace`.
+    pub fn sample_in(&self, img: &TGAImage, uv: &Vec2f, colorspace: ColorSpace) -> Vec4f {
+        let (w, h) = (img.get_width(), img.get_height());
+        if img.buffer().is_none() || w <= 0 || h <= 0 {
+            return Vec4f::new();
+        }
+        let x = uv.x * w as f32;
+        let y = uv.y * h as f32;
+        match self.filter {
+            Filter::Nearest => self.texel(img, x.floor() as i32, y.floor() as i32, colorspace),
+            Filter::Bilinear => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i32, y0 as i32);
+                let lerp = |a: Vec4f, b: Vec4f, t: f32| {
+                    Vec4f::new_args(
+                        a.x + (b.x - a.x) * t,
+                        a.y + (b.y - a.y) * t,
+                        a.z + (b.z - a.z) * t,
+                        a.a + (b.a - a.a) * t,
+                    )
+                };
+                let texel = |x, y| self.texel(img, x, y, colorspace);
+                let top = lerp(texel(x0, y0), texel(x0 + 1, y0), fx);
+                let bottom = lerp(texel(x0, y0 + 1), texel(x0 + 1, y0 + 1), fx);
+                lerp(top, bottom, fy)
+            }
+        }
+    }
+}
+
+/// Linear intensity of an sRGB byte, from a table built on first use.
+fn srgb_to_linear(v: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.; 256];
+        for (i, t) in table.iter_mut().enumerate() {
+            *t = srgb_decode(i as f32 / 255.);
+        }
+        table
+    })[v as usize]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::MipFilter;
+    use crate::tga::{TGAColor, TGAFormat};
+
+    fn checker() -> TGAImage {
+        // 2x2: black, white / red, blue
+        let mut img = TGAImage::new_dimensions(2, 2, TGAFormat::RGB as i32);
+        img.set(1, 0, &TGAColor::new_rgba(255, 255, 255, 255));
+        img.set(0, 1, &TGAColor::new_rgba(255, 0, 0, 255));
+        img.set(1, 1, &TGAColor::new_rgba(0, 0, 255, 255));
+        img
+    }
+
+    fn rgb(c: Vec4f) -> [f32; 3] {
+        [c.x, c.y, c.z]
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let wrapped: Vec<i32> = (-4..8).map(|i| Wrap::Repeat.apply(i, 3)).collect();
+        assert_eq!(wrapped, [2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1]);
+        let wrapped: Vec<i32> = (-4..8).map(|i| Wrap::Clamp.apply(i, 3)).collect();
+        assert_eq!(wrapped, [0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2, 2]);
+        let wrapped: Vec<i32> = (-4..8).map(|i| Wrap::Mirror.apply(i, 3)).collect();
+        assert_eq!(wrapped, [2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0, 1]);
+    }
+
+    #[test]
+    fn nearest_lookups() {
+        let img = checker();
+        let s = Sampler::new(Filter::Nearest, Wrap::Repeat);
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0.75, 0.25))),
+            [1., 1., 1.]
+        );
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(1.25, 0.75))),
+            [1., 0., 0.]
+        );
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(-0.25, -0.25))),
+            [0., 0., 1.]
+        );
+
+        let s = Sampler::new(Filter::Nearest, Wrap::Clamp);
+        assert_eq!(rgb(s.sample(&img, &Vec2f::new_args(-3., 7.))), [1., 0., 0.]);
+        let s = Sampler::new(Filter::Nearest, Wrap::Mirror);
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(1.25, 0.25))),
+            [1., 1., 1.]
+        );
+        assert_eq!(s.sample(&img, &Vec2f::new_args(0.25, 0.25)).a, 1.);
+    }
+
+    #[test]
+    fn bilinear_blends_neighbours() {
+        let img = checker();
+        let s = Sampler::new(Filter::Bilinear, Wrap::Clamp);
+        // texel centers return the texel itself
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0.75, 0.75))),
+            [0., 0., 1.]
+        );
+        // the middle averages all four
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0.5, 0.5))),
+            [0.5, 0.25, 0.5]
+        );
+        // halfway along the top row
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0.5, 0.25))),
+            [0.5, 0.5, 0.5]
+        );
+
+        // repeat blends across the edge with the opposite side
+        let s = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0., 0.25))),
+            [0.5, 0.5, 0.5]
+        );
+        let s = Sampler::new(Filter::Bilinear, Wrap::Clamp);
+        assert_eq!(
+            rgb(s.sample(&img, &Vec2f::new_args(0., 0.25))),
+            [0., 0., 0.]
+        );
+    }
+
+    #[test]
+    fn level_of_detail_and_trilinear_blending() {
+        // 4x4 white, so level 1 (2x2) is white too; level 2 is set to black by hand
+        let mut img = TGAImage::new_dimensions(4, 4, TGAFormat::Grayscale as i32);
+        for y in 0..4 {
+            for x in 0..4 {
+                img.set(x, y, &TGAColor::new_raw(&[255], 1));
+            }
+        }
+        let tex = Texture::with_mipmaps(img, MipFilter::Box, ColorSpace::Linear);
+        assert_eq!(tex.levels(), 3);
+        let s = Sampler::trilinear(Wrap::Repeat);
+        // one texel per pixel is level 0, two is level 1
+        let d = |t: f32| Vec2f::new_args(t / 4., 0.);
+        assert_eq!(s.lod(&tex, &d(1.), &Vec2f::new()), 0.);
+        assert_eq!(s.lod(&tex, &Vec2f::new(), &d(2.)), 1.);
+        assert_eq!(s.lod(&tex, &d(2.), &d(4.)), 2.);
+
+        let mut black = tex.clone();
+        black.level_mut(2).set(0, 0, &TGAColor::new_raw(&[0], 1));
+        let uv = Vec2f::new_args(0.5, 0.5);
+        assert_eq!(s.sample_lod(&black, &uv, 0.).x, 1.);
+        assert_eq!(s.sample_lod(&black, &uv, 1.25).x, 0.75);
+        assert_eq!(s.sample_lod(&black, &uv, 1.75).x, 0.25);
+        // past the end of the chain and below the base level
+        assert_eq!(s.sample_lod(&black, &uv, 9.).x, 0.);
+        assert_eq!(s.sample_grad(&black, &uv, &d(0.1), &d(0.1)).x, 1.);
+
+        let s = Sampler {
+            mipmap: MipmapMode::Nearest,
+            ..s
+        };
+        assert_eq!(s.sample_lod(&black, &uv, 1.4).x, 1.);
+        assert_eq!(s.sample_lod(&black, &uv, 1.6).x, 0.);
+        let s = Sampler::new(Filter::Bilinear, Wrap::Repeat);
+        assert_eq!(s.sample_lod(&black, &uv, 2.).x, 1.);
+    }
+
+    #[test]
+    fn anisotropic_footprints() {
+        // 16x16 horizontal stripes, white on even rows
+        let mut img = TGAImage::new_dimensions(16, 16, TGAFormat::Grayscale as i32);
+        for y in (0..16).step_by(2) {
+            for x in 0..16 {
+                img.set(x, y, &TGAColor::new_raw(&[255], 1));
+            }
+        }
+        let tex = Texture::with_mipmaps(img, MipFilter::Box, ColorSpace::Linear);
+        // a footprint 8 texels long along the stripes and 1 texel across them
+        let uv = Vec2f::new_args(0.5, 2.5 / 16.);
+        let (dx, dy) = (Vec2f::new_args(0.5, 0.), Vec2f::new_args(0., 1. / 16.));
+
+        // trilinear sizes the footprint by its long axis and blurs the stripes to gray
+        let c = Sampler::trilinear(Wrap::Repeat).sample_grad(&tex, &uv, &dx, &dy);
+        assert!((c.x - 0.5).abs() < 0.01, "{}", c.x);
+        // eight samples along the stripe keep it sharp, in either axis order
+        let s = Sampler::anisotropic(Wrap::Repeat, 16.);
+        assert_eq!(s.sample_grad(&tex, &uv, &dx, &dy).x, 1.);
+        assert_eq!(s.sample_grad(&tex, &uv, &dy, &dx).x, 1.);
+        // two samples only reach level 2, which has already averaged the stripes away
+        let c = Sampler::anisotropic(Wrap::Repeat, 2.).sample_grad(&tex, &uv, &dx, &dy);
+        assert!((c.x - 0.5).abs() < 0.01, "{}", c.x);
+        // isotropic footprints are unaffected
+        assert_eq!(s.sample_grad(&tex, &uv, &dy, &dy).x, 1.);
+
+        // out of range limits are clamped, also when set directly
+        assert_eq!(Sampler::anisotropic(Wrap::Repeat, 1e9).max_anisotropy, 16.);
+        assert_eq!(
+            Sampler::anisotropic(Wrap::Repeat, f32::NAN).max_anisotropy,
+            1.
+        );
+        assert_eq!(Sampler::anisotropic(Wrap::Repeat, 0.).max_anisotropy, 1.);
+        let zero = Vec2f::new_args(0., 0.);
+        for max_anisotropy in [1e9, f32::INFINITY, f32::NAN] {
+            let s = Sampler {
+                max_anisotropy,
+                ..Sampler::trilinear(Wrap::Repeat)
+            };
+            // a footprint with no width used to ask for infinitely many samples
+            assert!(s.sample_grad(&tex, &uv, &dx, &zero).x.is_finite());
+        }
+    }
+
+    #[test]
+    fn srgb_textures_filter_in_linear() {
+        let img = checker();
+        let s = Sampler::new(Filter::Bilinear, Wrap::Clamp);
+        let mid = Vec2f::new_args(0.5, 0.25);
+        // halfway between black and white
+        assert_eq!(s.sample_in(&img, &mid, ColorSpace::Linear).x, 0.5);
+        assert_eq!(s.sample_in(&img, &mid, ColorSpace::Srgb).x, 0.5);
+        let tex = Texture::new(img, ColorSpace::Srgb);
+        let c = s.sample_lod(&tex, &Vec2f::new_args(0.25, 0.25), 0.);
+        assert_eq!([c.x, c.a], [0., 1.]);
+        // 188 is about half the light of 255
+        let mut img = TGAImage::new_dimensions(1, 1, TGAFormat::Grayscale as i32);
+        img.set(0, 0, &TGAColor::new_raw(&[188], 1));
+        let c = s.sample_in(&img, &mid, ColorSpace::Srgb);
+        assert!((c.x - 0.5).abs() < 0.01, "{}", c.x);
+    }
+
+    #[test]
+    fn grayscale_and_empty_images() {
+        let mut img = TGAImage::new_dimensions(1, 1, TGAFormat::Grayscale as i32);
+        img.set(0, 0, &TGAColor::new_raw(&[51], 1));
+        let c = Sampler::default().sample(&img, &Vec2f::new_args(3.3, -0.4));
+        assert_eq!([c.x, c.y, c.z, c.a], [0.2, 0.2, 0.2, 1.]);
+
+        let c = Sampler::default().sample(&TGAImage::new(), &Vec2f::new_args(0.5, 0.5));
+        assert_eq!([c.x, c.y, c.z, c.a], [0.; 4]);
+    }
+}
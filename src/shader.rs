@@ -0,0 +1,150 @@
+use crate::{
+    geometry::*,
+    hdr::srgb_decode,
+    model::Model,
+    our_gl::{IShader, QuadDerivatives, ViewBundle},
+    sampler::Sampler,
+};
+
+/*
+    Normal mapped Phong shader
+
+    The shader of the tinyrenderer lessons: a tangent space
+    normal map, a specular exponent map and a diffuse map,
+    lit by one directional light, all sampled with texture
+    coordinate derivatives so mipmapping works.
+*/
+
+/// Shades a `Model` with its diffuse, tangent space normal and specular maps.
+pub struct Shader<'a> {
+    model: &'a Model,
+    uniform_l: Vec3f,
+    varying_uv: Matrix,
+    varying_nrm: Matrix,
+    view_tri: Matrix,
+    sampler: Sampler,
+}
+
+impl<'a> Shader<'a> {
+    /// `light_dir` points towards the light, in world space.
+    pub fn new(m: &'a Model, view_bundle: &ViewBundle, light_dir: Vec3f, sampler: Sampler) -> Self {
+        Self {
+            model: m,
+            uniform_l: *Vec3f::from_vec(&proj_refactor(
+                Into::<Vec<f32>>::into(view_bundle.ModelView.clone() * embed(&light_dir, Some(0.))),
+                3,
+            ))
+            .normalize(),
+            varying_uv: Matrix::new(Some(2), Some(3)),
+            varying_nrm: Matrix::new(Some(3), Some(3)),
+            view_tri: Matrix::new(Some(3), Some(3)),
+            sampler,
+        }
+    }
+}
+
+impl<'a> IShader for Shader<'a> {
+    fn vertex(&mut self, iface: i32, nthvert: i32, view_bundle: &ViewBundle) -> Vec4f {
+        self.varying_uv.set_col(
+            nthvert,
+            &[
+                self.model.uv(iface, nthvert)[0],
+                self.model.uv(iface, nthvert)[1],
+            ],
+        );
+        self.varying_nrm.set_col(
+            nthvert,
+            &proj_refactor(
+                Into::<Vec<f32>>::into(
+                    (view_bundle.ModelView.clone()).invert_transpose()
+                        * embed(&self.model.norm(iface, nthvert), Some(0.)),
+                ),
+                3,
+            ),
+        );
+        let mut gl_Position =
+            view_bundle.ModelView.clone() * embed(&self.model.vert(iface, nthvert), None);
+        self.view_tri.set_col(
+            nthvert,
+            &proj_refactor(Into::<Vec<f32>>::into(gl_Position), 3),
+        );
+        gl_Position = view_bundle.Projection.clone() * gl_Position;
+        gl_Position
+    }
+
+    fn fragment(&self, bar: Vec3f, deriv: &QuadDerivatives) -> (bool, Vec4f) {
+        let bn = (self.varying_nrm.clone() * [bar[0], bar[1], bar[2]])
+            .normalize()
+            .to_owned();
+        let uv = Vec2f::new_args(
+            (self.varying_uv.clone() * [bar[0], bar[1], bar[2]])[0],
+            (self.varying_uv.clone() * [bar[0], bar[1], bar[2]])[1],
+        );
+        let duv = |d: Vec3f| {
+            let v = self.varying_uv.clone() * [d[0], d[1], d[2]];
+            Vec2f::new_args(v[0], v[1])
+        };
+        let (duv_dx, duv_dy) = (duv(deriv.dx), duv(deriv.dy));
+        // TODO: this matrix definition might be wrong
+        let mut AI = Matrix::new(Some(3), Some(3));
+        let col0 = self.view_tri.col(0);
+        let col1 = self.view_tri.col(1);
+        let col2 = self.view_tri.col(2);
+        AI[0] = [col1[0] - col0[0], col1[1] - col0[1], col1[2] - col0[2], 0.];
+        AI[1] = [col2[0] - col0[0], col2[1] - col0[1], col2[2] - col0[2], 0.];
+        AI[2] = [bn[0], bn[1], bn[2], 0.];
+        AI = AI.invert();
+
+        let mut i = AI.clone()
+            * [
+                self.varying_uv[0][1] - self.varying_uv[0][0],
+                self.varying_uv[0][2] - self.varying_uv[0][0],
+                0.,
+            ];
+        let mut j = AI.clone()
+            * [
+                self.varying_uv[1][1] - self.varying_uv[1][0],
+                self.varying_uv[1][2] - self.varying_uv[1][0],
+                0.,
+            ];
+        let mut B = Matrix::new(Some(3), Some(3));
+        B[0] = [
+            i.normalize().to_owned()[0],
+            i.normalize().to_owned()[1],
+            i.normalize().to_owned()[2],
+            0.,
+        ];
+        B[1] = [
+            j.normalize().to_owned()[0],
+            j.normalize().to_owned()[1],
+            j.normalize().to_owned()[2],
+            0.,
+        ];
+        B[2] = [bn[0], bn[1], bn[2], 0.];
+        B = B.transpose();
+
+        let nm = self.model.normal(&self.sampler, &uv, &duv_dx, &duv_dy);
+        let n = (B * [nm[0], nm[1], nm[2]]).normalize().to_owned();
+        let diff = 0f32.max(n * self.uniform_l);
+        let r = (n * (n * self.uniform_l) * 2. - self.uniform_l)
+            .normalize()
+            .to_owned();
+        // the viewer is along +z in view space
+        let spec = r.z.max(0.).powf(
+            5. + Shader::sample2D_grad(&self.sampler, self.model.specular(), &uv, &duv_dx, &duv_dy)
+                .x
+                * 255.,
+        );
+
+        let c = Shader::sample2D_grad(&self.sampler, self.model.diffuse(), &uv, &duv_dx, &duv_dy);
+        // texels come out of the sampler linear, so lighting adds up physically; the ambient
+        // term is the 10/255 the shader used to add to sRGB values
+        let ambient = srgb_decode(10. / 255.);
+        // no clamping: highlights above 1.0 are left to the tone mapper
+        let shade = |v: f32| ambient + v * (diff + spec);
+        (
+            false,
+            Vec4f::new_args(shade(c.x), shade(c.y), shade(c.z), 1.),
+        )
+    }
+}
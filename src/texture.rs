@@ -0,0 +1,305 @@
+use std::f32::consts::PI;
+
+use crate::hdr::{srgb_decode, srgb_encode};
+use crate::tga::TGAImage;
+
+/*
+    Textures and mipmaps
+
+    A Texture is a TGAImage plus its chain of successively
+    halved copies, down to 1x1. Levels are built with a
+    separable resampling filter: a plain box average or a
+    Kaiser-windowed sinc, which keeps more detail at the
+    cost of slight ringing. Color textures are stored sRGB
+    encoded and are filtered in linear space; data like
+    normal and specular maps is stored as is.
+*/
+
+/// Taps reach this many destination pixels to each side of the center for the Kaiser filter.
+const KAISER_RADIUS: f32 = 2.;
+const KAISER_BETA: f32 = 4.;
+
+/// How the texel bytes of a texture relate to linear values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    /// sRGB encoded color; alpha is always linear.
+    Srgb,
+    /// Data used as is.
+    Linear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MipFilter {
+    Box,
+    Kaiser,
+}
+
+impl MipFilter {
+    fn radius(self) -> f32 {
+        match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Kaiser => KAISER_RADIUS,
+        }
+    }
+
+    /// Filter weight at distance `d`, in destination pixels, from the destination pixel center.
+    fn weight(self, d: f32) -> f32 {
+        match self {
+            MipFilter::Box => {
+                if d.abs() < 0.5 {
+                    1.
+                } else {
+                    0.
+                }
+            }
+            MipFilter::Kaiser => {
+                let t = d / KAISER_RADIUS;
+                if t.abs() >= 1. {
+                    return 0.;
+                }
+                let sinc = if d == 0. {
+                    1.
+                } else {
+                    (PI * d).sin() / (PI * d)
+                };
+                sinc * bessel_i0(KAISER_BETA * (1. - t * t).sqrt()) / bessel_i0(KAISER_BETA)
+            }
+        }
+    }
+}
+
+/// Modified Bessel function of the first kind, order zero, by its power series.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.;
+    let mut term = 1.;
+    let q = x * x / 4.;
+    for k in 1..32 {
+        term *= q / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Resamples `n` values spaced `stride` apart in `src` down to `m` values, edges clamped.
+fn resample_line(filter: MipFilter, src: &[f32], n: usize, stride: usize, m: usize) -> Vec<f32> {
+    let scale = n as f32 / m as f32;
+    let reach = filter.radius() * scale;
+    (0..m)
+        .map(|x| {
+            let center = (x as f32 + 0.5) * scale;
+            let first = (center - reach).floor() as i32;
+            let last = (center + reach).ceil() as i32;
+            let (mut sum, mut total) = (0., 0.);
+            for i in first..=last {
+                let w = filter.weight((i as f32 + 0.5 - center) / scale);
+                if w != 0. {
+                    sum += w * src[i.clamp(0, n as i32 - 1) as usize * stride];
+                    total += w;
+                }
+            }
+            sum / total
+        })
+        .collect()
+}
+
+impl TGAImage {
+    /// Copy at half the size (rounded down, at least 1x1) for the next mip level. Color channels
+    /// of sRGB images are averaged as linear intensities.
+    pub fn downsample(&self, filter: MipFilter, colorspace: ColorSpace) -> TGAImage {
+        let (w, h, bpp) = (
+            self.get_width() as usize,
+            self.get_height() as usize,
+            self.get_bytespp() as usize,
+        );
+        let src = match self.buffer() {
+            Some(src) if w > 0 && h > 0 => src,
+            _ => return self.clone(),
+        };
+        let (w2, h2) = ((w / 2).max(1), (h / 2).max(1));
+        let mut out = TGAImage::new_dimensions(w2 as i32, h2 as i32, bpp as i32);
+        let dst = out.buffer_mut().unwrap();
+        for c in 0..bpp {
+            let srgb = colorspace == ColorSpace::Srgb && c < 3;
+            let channel: Vec<f32> = src[c..]
+                .iter()
+                .step_by(bpp)
+                .map(|&v| {
+                    if srgb {
+                        srgb_decode(v as f32 / 255.) * 255.
+                    } else {
+                        v as f32
+                    }
+                })
+                .collect();
+            // horizontal pass, then vertical
+            let mut rows = Vec::with_capacity(w2 * h);
+            for y in 0..h {
+                rows.extend(resample_line(filter, &channel[y * w..], w, 1, w2));
+            }
+            for x in 0..w2 {
+                let column = resample_line(filter, &rows[x..], h, w2, h2);
+                for (y, mut v) in column.into_iter().enumerate() {
+                    if srgb {
+                        v = srgb_encode((v / 255.).clamp(0., 1.)) * 255.;
+                    }
+                    dst[(x + y * w2) * bpp + c] = v.round().clamp(0., 255.) as u8;
+                }
+            }
+        }
+        out
+    }
+
+    /// This image followed by successively downsampled copies, ending at 1x1.
+    pub fn mip_chain(&self, filter: MipFilter, colorspace: ColorSpace) -> Vec<TGAImage> {
+        let mut levels = vec![self.clone()];
+        loop {
+            let last = levels.last().unwrap();
+            if last.buffer().is_none() || (last.get_width() <= 1 && last.get_height() <= 1) {
+                break;
+            }
+            let next = last.downsample(filter, colorspace);
+            levels.push(next);
+        }
+        levels
+    }
+}
+
+/// An image with its mip levels, level 0 being the full resolution one.
+#[derive(Clone)]
+pub struct Texture {
+    levels: Vec<TGAImage>,
+    colorspace: ColorSpace,
+}
+
+impl Texture {
+    /// A texture without mip levels.
+    pub fn new(img: TGAImage, colorspace: ColorSpace) -> Self {
+        Texture {
+            levels: vec![img],
+            colorspace,
+        }
+    }
+
+    pub fn with_mipmaps(img: TGAImage, filter: MipFilter, colorspace: ColorSpace) -> Self {
+        Texture {
+            levels: img.mip_chain(filter, colorspace),
+            colorspace,
+        }
+    }
+
+    pub fn colorspace(&self) -> ColorSpace {
+        self.colorspace
+    }
+
+    pub fn base(&self) -> &TGAImage {
+        &self.levels[0]
+    }
+
+    /// Mip level `i`, or the smallest one if the chain is shorter.
+    pub fn level(&self, i: usize) -> &TGAImage {
+        &self.levels[i.min(self.levels.len() - 1)]
+    }
+
+    pub fn level_mut(&mut self, i: usize) -> &mut TGAImage {
+        let last = self.levels.len() - 1;
+        &mut self.levels[i.min(last)]
+    }
+
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tga::{TGAColor, TGAFormat};
+
+    fn gray(w: i32, h: i32, f: impl Fn(i32, i32) -> u8) -> TGAImage {
+        let mut img = TGAImage::new_dimensions(w, h, TGAFormat::Grayscale as i32);
+        for y in 0..h {
+            for x in 0..w {
+                img.set(x, y, &TGAColor::new_raw(&[f(x, y)], 1));
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn box_filter_averages_blocks() {
+        let img = gray(4, 2, |x, y| (x * 10 + y * 100) as u8);
+        let half = img.downsample(MipFilter::Box, ColorSpace::Linear);
+        assert_eq!((half.get_width(), half.get_height()), (2, 1));
+        assert_eq!(half.buffer().unwrap(), [55, 75]);
+
+        // odd sizes average the three source pixels a destination pixel covers
+        let img = gray(3, 1, |x, _| [0, 30, 90][x as usize]);
+        assert_eq!(
+            img.downsample(MipFilter::Box, ColorSpace::Linear)
+                .buffer()
+                .unwrap(),
+            [40]
+        );
+    }
+
+    #[test]
+    fn chain_goes_down_to_one_pixel() {
+        let img = TGAImage::new_dimensions(10, 3, TGAFormat::RGB as i32);
+        let sizes: Vec<(i32, i32)> = img
+            .mip_chain(MipFilter::Box, ColorSpace::Linear)
+            .iter()
+            .map(|l| (l.get_width(), l.get_height()))
+            .collect();
+        assert_eq!(sizes, [(10, 3), (5, 1), (2, 1), (1, 1)]);
+        let tex = Texture::with_mipmaps(img, MipFilter::Kaiser, ColorSpace::Linear);
+        assert_eq!(tex.levels(), 4);
+        assert_eq!(tex.level(9).get_width(), 1);
+        assert_eq!(
+            Texture::with_mipmaps(TGAImage::new(), MipFilter::Box, ColorSpace::Srgb).levels(),
+            1
+        );
+    }
+
+    #[test]
+    fn srgb_levels_average_light() {
+        // black and white average to half the light, which is 188 in sRGB rather than 128
+        let img = gray(2, 1, |x, _| if x == 0 { 0 } else { 255 });
+        assert_eq!(
+            img.downsample(MipFilter::Box, ColorSpace::Srgb)
+                .buffer()
+                .unwrap(),
+            [188]
+        );
+        assert_eq!(
+            img.downsample(MipFilter::Box, ColorSpace::Linear)
+                .buffer()
+                .unwrap(),
+            [128]
+        );
+
+        // alpha is averaged as is
+        let mut img = TGAImage::new_dimensions(2, 1, TGAFormat::RGBA as i32);
+        img.set(1, 0, &TGAColor::new_rgba(255, 255, 255, 255));
+        let half = img.downsample(MipFilter::Box, ColorSpace::Srgb);
+        assert_eq!(half.buffer().unwrap(), [188, 188, 188, 128]);
+    }
+
+    #[test]
+    fn kaiser_filter() {
+        // flat areas stay flat
+        let flat = gray(16, 16, |_, _| 77).downsample(MipFilter::Kaiser, ColorSpace::Linear);
+        assert!(flat.buffer().unwrap().iter().all(|&v| v == 77));
+
+        // the filter is symmetric, so away from the edges a ramp resamples to its value at the
+        // destination pixel center, like the box filter
+        let ramp =
+            gray(16, 1, |x, _| (x * 10) as u8).downsample(MipFilter::Kaiser, ColorSpace::Linear);
+        for x in 2..6 {
+            let v = ramp.get(x, 0)[0] as i32;
+            assert!((v - (20 * x + 5)).abs() <= 1, "{}", v);
+        }
+    }
+}
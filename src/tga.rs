@@ -1,8 +1,14 @@
 use byteorder::{LittleEndian, ReadBytesExt};
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs::File;
-use std::io::{self, Error};
-use std::io::{prelude::*, BufReader};
+use std::io::{self, Error, ErrorKind};
+use std::io::{prelude::*, BufWriter, Cursor, SeekFrom};
 use std::ops::{Index, IndexMut};
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+const FOOTER_SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+const EXTENSION_AREA_SIZE: u16 = 495;
 
 #[derive(Debug)]
 struct TGAHeader {
@@ -39,12 +45,600 @@ impl TGAHeader {
     }
 }
 
+/// Reasons a TGA file can fail to decode. Offsets are in bytes from the start of the file.
+#[derive(Debug)]
+pub enum TgaError {
+    Io(io::Error),
+    /// The data ended inside the field or packet starting at `offset`.
+    Truncated {
+        offset: u64,
+    },
+    UnsupportedType {
+        type_code: u8,
+    },
+    BadDimensions {
+        width: u16,
+        height: u16,
+    },
+    /// The image exceeds the `DecodeLimits` it was decoded with.
+    TooLarge {
+        width: u16,
+        height: u16,
+    },
+    /// Unsupported pixel or color map entry size for the image type.
+    BadBitDepth {
+        bits: u8,
+    },
+    /// The RLE packet at `offset` runs past the last pixel of the image.
+    PixelOverflow {
+        offset: u64,
+    },
+    MissingColorMap,
+    BadColorMapIndex {
+        index: usize,
+    },
+}
+
+impl fmt::Display for TgaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TgaError::Io(e) => write!(f, "{e}"),
+            TgaError::Truncated { offset } => write!(f, "data truncated at byte {offset}"),
+            TgaError::UnsupportedType { type_code } => {
+                write!(f, "unsupported image type {type_code}")
+            }
+            TgaError::BadDimensions { width, height } => {
+                write!(f, "bad image dimensions {width}x{height}")
+            }
+            TgaError::TooLarge { width, height } => {
+                write!(
+                    f,
+                    "image dimensions {width}x{height} exceed the decoding limits"
+                )
+            }
+            TgaError::BadBitDepth { bits } => write!(f, "unsupported bit depth {bits}"),
+            TgaError::PixelOverflow { offset } => {
+                write!(f, "RLE packet at byte {offset} overflows the image")
+            }
+            TgaError::MissingColorMap => write!(f, "color-mapped image without a color map"),
+            TgaError::BadColorMapIndex { index } => {
+                write!(f, "color map index {index} out of range")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TgaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TgaError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TgaError {
+    fn from(e: io::Error) -> Self {
+        TgaError::Io(e)
+    }
+}
+
+/// Wraps the input and keeps track of the current byte offset for error reporting.
+struct TgaReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Read> TgaReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, offset: 0 }
+    }
+
+    fn eof_error(&self, e: io::Error) -> TgaError {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            TgaError::Truncated {
+                offset: self.offset,
+            }
+        } else {
+            TgaError::Io(e)
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), TgaError> {
+        match self.inner.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(())
+            }
+            Err(e) => Err(self.eof_error(e)),
+        }
+    }
+
+    fn skip(&mut self, n: u64) -> Result<(), TgaError> {
+        let skipped = io::copy(&mut (&mut self.inner).take(n), &mut io::sink())?;
+        self.offset += skipped;
+        if skipped < n {
+            return Err(TgaError::Truncated {
+                offset: self.offset,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_u8(&mut self) -> Result<u8, TgaError> {
+        match self.inner.read_u8() {
+            Ok(v) => {
+                self.offset += 1;
+                Ok(v)
+            }
+            Err(e) => Err(self.eof_error(e)),
+        }
+    }
+
+    fn read_u16(&mut self) -> Result<u16, TgaError> {
+        match self.inner.read_u16::<LittleEndian>() {
+            Ok(v) => {
+                self.offset += 2;
+                Ok(v)
+            }
+            Err(e) => Err(self.eof_error(e)),
+        }
+    }
+
+    fn read_u32(&mut self) -> Result<u32, TgaError> {
+        match self.inner.read_u32::<LittleEndian>() {
+            Ok(v) => {
+                self.offset += 4;
+                Ok(v)
+            }
+            Err(e) => Err(self.eof_error(e)),
+        }
+    }
+}
+
+impl<R: Read + Seek> TgaReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, TgaError> {
+        self.offset = self.inner.seek(pos)?;
+        Ok(self.offset)
+    }
+
+    /// Number of bytes left in the input after the current offset.
+    fn remaining(&mut self) -> Result<u64, TgaError> {
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(self.offset))?;
+        Ok(end.saturating_sub(self.offset))
+    }
+}
+
+/// Upper bounds on the images accepted by the TGA, PNG, JPEG and Netpbm decoders, checked before
+/// pixel memory is allocated. Independently of these, a decoded TGA must fit in `i32::MAX` bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeLimits {
+    pub max_width: u16,
+    pub max_height: u16,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_width: 16384,
+            max_height: 16384,
+        }
+    }
+}
+
+/// Counts the bytes written, so the TGA 2.0 areas can refer to each other by offset.
+struct TgaWriter<W> {
+    inner: W,
+    offset: u64,
+}
+
+impl<W: Write> TgaWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, offset: 0 }
+    }
+}
+
+impl<W: Write> Write for TgaWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Palette of a color-mapped image, stored as BGR or BGRA entries. 16-bit entries keep their
+/// attribute bit as alpha when the image descriptor declares one, and are opaque otherwise.
+struct ColorMap {
+    origin: usize,
+    bytespp: usize,
+    entries: Vec<u8>,
+}
+
+impl ColorMap {
+    fn read<R: Read>(f: &mut TgaReader<R>, header: &TGAHeader) -> Result<Self, TgaError> {
+        let depth = header.color_map_depth;
+        let alpha_bit = header.image_descriptor & 0x0f != 0;
+        let bytespp = match depth {
+            15 | 24 => TGAFormat::RGB as usize,
+            16 | 32 => TGAFormat::RGBA as usize,
+            _ => return Err(TgaError::BadBitDepth { bits: depth }),
+        };
+        let mut entries = Vec::with_capacity(header.color_map_length as usize * bytespp);
+        let mut entry = [0u8; 4];
+        for _ in 0..header.color_map_length {
+            let entry = &mut entry[..(depth as usize + 7) >> 3];
+            f.read_exact(entry)?;
+            if depth == 15 || depth == 16 {
+                let mut bgra = unpack_a1r5g5b5([entry[0], entry[1]]);
+                if !alpha_bit {
+                    bgra[3] = 255;
+                }
+                entries.extend_from_slice(&bgra[..bytespp]);
+            } else {
+                entries.extend_from_slice(entry);
+            }
+        }
+        Ok(Self {
+            origin: header.color_map_origin as usize,
+            bytespp,
+            entries,
+        })
+    }
+
+    fn entry(&self, index: usize) -> Option<&[u8]> {
+        let i = index.checked_sub(self.origin)? * self.bytespp;
+        self.entries.get(i..i + self.bytespp)
+    }
+}
+
+/// How the alpha channel of an image should be interpreted (TGA 2.0 attributes type).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TGAAlphaType {
+    NoAlpha = 0,
+    UndefinedIgnore = 1,
+    UndefinedRetain = 2,
+    Alpha = 3,
+    Premultiplied = 4,
+}
+
+impl TGAAlphaType {
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(Self::NoAlpha),
+            1 => Some(Self::UndefinedIgnore),
+            2 => Some(Self::UndefinedRetain),
+            3 => Some(Self::Alpha),
+            4 => Some(Self::Premultiplied),
+            _ => None,
+        }
+    }
+}
+
+/// A tagged entry of the TGA 2.0 developer area.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TGADeveloperField {
+    pub tag: u16,
+    pub data: Vec<u8>,
+}
+
+/// TGA 2.0 metadata, read from and written to the extension and developer areas.
+///
+/// Strings are stored in fixed-size fields and get truncated on write: 40 bytes for
+/// `author`, `job_name` and `software_id`, and 4 lines of 80 bytes for `comments`.
+#[derive(Clone, Default)]
+pub struct TGAMetadata {
+    pub author: String,
+    pub comments: String,
+    pub timestamp: Option<PrimitiveDateTime>,
+    pub job_name: String,
+    pub software_id: String,
+    pub gamma: Option<f32>,
+    /// Written as `Alpha` for RGBA images and `NoAlpha` otherwise when unset.
+    pub alpha_type: Option<TGAAlphaType>,
+    pub postage_stamp: Option<Box<TGAImage>>,
+    pub developer_fields: Vec<TGADeveloperField>,
+    /// Why parts of the metadata of a decoded file were skipped. Not written.
+    pub warnings: Vec<String>,
+}
+
+impl TGAMetadata {
+    /// Reads whatever TGA 2.0 metadata follows the image data. The areas are optional and often
+    /// filled with junk by writers, so problems with them end up in `warnings` rather than
+    /// failing the decode.
+    fn read<R: Read + Seek>(
+        f: &mut TgaReader<R>,
+        stamp_bytespp: Option<i32>,
+        image_descriptor: u8,
+    ) -> Self {
+        let mut metadata = Self::default();
+        let (extension_offset, developer_offset, len) = match Self::read_footer(f) {
+            Ok(Some(footer)) => footer,
+            Ok(None) => return metadata,
+            Err(e) => {
+                metadata.warnings.push(format!("footer: {}", e));
+                return metadata;
+            }
+        };
+
+        if developer_offset != 0 {
+            match Self::read_developer_area(f, developer_offset, len) {
+                Ok(fields) => metadata.developer_fields = fields,
+                Err(e) => metadata.warnings.push(format!("developer area: {}", e)),
+            }
+        }
+        if extension_offset != 0 {
+            match metadata.read_extension_area(f, extension_offset) {
+                Ok(Some(stamp_offset)) => {
+                    if let Some(bytespp) = stamp_bytespp {
+                        match Self::read_postage_stamp(f, stamp_offset, bytespp, image_descriptor) {
+                            Ok(stamp) => metadata.postage_stamp = stamp.map(Box::new),
+                            Err(e) => metadata.warnings.push(format!("postage stamp: {}", e)),
+                        }
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => metadata.warnings.push(format!("extension area: {}", e)),
+            }
+        }
+        metadata
+    }
+
+    /// The extension and developer area offsets and the input length, if there is a TGA 2.0
+    /// footer.
+    fn read_footer<R: Read + Seek>(
+        f: &mut TgaReader<R>,
+    ) -> Result<Option<(u64, u64, u64)>, TgaError> {
+        let len = f.seek(SeekFrom::End(0))?;
+        if len < 18 + 26 {
+            return Ok(None);
+        }
+        f.seek(SeekFrom::End(-26))?;
+        let extension_offset = f.read_u32()? as u64;
+        let developer_offset = f.read_u32()? as u64;
+        let mut signature = [0u8; 18];
+        f.read_exact(&mut signature)?;
+        if &signature != FOOTER_SIGNATURE {
+            return Ok(None);
+        }
+        Ok(Some((extension_offset, developer_offset, len)))
+    }
+
+    fn read_developer_area<R: Read + Seek>(
+        f: &mut TgaReader<R>,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<TGADeveloperField>, TgaError> {
+        f.seek(SeekFrom::Start(offset))?;
+        let count = f.read_u16()?;
+        let mut directory = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            directory.push((f.read_u16()?, f.read_u32()? as u64, f.read_u32()? as usize));
+        }
+        let mut fields = Vec::with_capacity(directory.len());
+        for (tag, offset, size) in directory {
+            if offset.saturating_add(size as u64) > len {
+                return Err(TgaError::Truncated { offset });
+            }
+            f.seek(SeekFrom::Start(offset))?;
+            let mut data = vec![0; size];
+            f.read_exact(&mut data)?;
+            fields.push(TGADeveloperField { tag, data });
+        }
+        Ok(fields)
+    }
+
+    /// Fills in the fields stored in the extension area and returns the postage stamp offset, if
+    /// there is one.
+    fn read_extension_area<R: Read + Seek>(
+        &mut self,
+        f: &mut TgaReader<R>,
+        offset: u64,
+    ) -> Result<Option<u64>, TgaError> {
+        f.seek(SeekFrom::Start(offset))?;
+        let mut ext = [0u8; EXTENSION_AREA_SIZE as usize];
+        f.read_exact(&mut ext[..2])?;
+        if u16::from_le_bytes([ext[0], ext[1]]) < EXTENSION_AREA_SIZE {
+            return Ok(None);
+        }
+        f.read_exact(&mut ext[2..])?;
+        let u16_at = |i: usize| u16::from_le_bytes([ext[i], ext[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([ext[i], ext[i + 1], ext[i + 2], ext[i + 3]]);
+
+        self.author = parse_fixed_str(&ext[2..43]);
+        self.comments = ext[43..367]
+            .chunks_exact(81)
+            .map(parse_fixed_str)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end_matches('\n')
+            .to_owned();
+        self.timestamp = parse_timestamp([
+            u16_at(367),
+            u16_at(369),
+            u16_at(371),
+            u16_at(373),
+            u16_at(375),
+            u16_at(377),
+        ]);
+        self.job_name = parse_fixed_str(&ext[379..420]);
+        self.software_id = parse_fixed_str(&ext[426..467]);
+        if u16_at(480) != 0 {
+            self.gamma = Some(u16_at(478) as f32 / u16_at(480) as f32);
+        }
+        self.alpha_type = TGAAlphaType::from_u8(ext[494]);
+
+        let stamp_offset = u32_at(486) as u64;
+        Ok(Some(stamp_offset).filter(|&o| o != 0))
+    }
+
+    fn read_postage_stamp<R: Read + Seek>(
+        f: &mut TgaReader<R>,
+        offset: u64,
+        bytespp: i32,
+        image_descriptor: u8,
+    ) -> Result<Option<TGAImage>, TgaError> {
+        f.seek(SeekFrom::Start(offset))?;
+        let w = f.read_u8()? as i32;
+        let h = f.read_u8()? as i32;
+        if w == 0 || h == 0 {
+            return Ok(None);
+        }
+        let mut stamp = TGAImage::new_dimensions(w, h, bytespp);
+        f.read_exact(stamp.data.as_mut().unwrap())?;
+        if bytespp == 2 {
+            stamp.expand_a1r5g5b5(image_descriptor & 0x0f != 0);
+        }
+        if image_descriptor & 0x20 == 0 {
+            stamp.flip_vertically();
+        }
+        if image_descriptor & 0x10 != 0 {
+            stamp.flip_horizontally();
+        }
+        Ok(Some(stamp))
+    }
+
+    /// Writes the developer area, postage stamp, extension area and footer after the image data.
+    fn write<W: Write>(&self, out: &mut TgaWriter<W>, bytespp: i32) -> io::Result<()> {
+        let mut developer_offset = 0;
+        if !self.developer_fields.is_empty() {
+            let mut directory = Vec::with_capacity(self.developer_fields.len());
+            for field in &self.developer_fields {
+                directory.push(out.offset as u32);
+                out.write_all(&field.data)?;
+            }
+            developer_offset = out.offset as u32;
+            out.write_all(&(self.developer_fields.len() as u16).to_le_bytes())?;
+            for (field, offset) in self.developer_fields.iter().zip(directory) {
+                out.write_all(&field.tag.to_le_bytes())?;
+                out.write_all(&offset.to_le_bytes())?;
+                out.write_all(&(field.data.len() as u32).to_le_bytes())?;
+            }
+        }
+
+        let mut stamp_offset = 0;
+        if let Some(stamp) = &self.postage_stamp {
+            if stamp.bytespp != bytespp || stamp.width > 255 || stamp.height > 255 {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "postage stamp must share the image format and fit in 255x255",
+                ));
+            }
+            stamp_offset = out.offset as u32;
+            out.write_all(&[stamp.width as u8, stamp.height as u8])?;
+            out.write_all(stamp.data.as_ref().unwrap())?;
+        }
+
+        let extension_offset = out.offset as u32;
+        out.write_all(&EXTENSION_AREA_SIZE.to_le_bytes())?;
+        write_fixed_str(out, &self.author, 41)?;
+        let mut lines = self.comments.lines();
+        for _ in 0..4 {
+            write_fixed_str(out, lines.next().unwrap_or(""), 81)?;
+        }
+        let timestamp = match self.timestamp {
+            Some(t) => [
+                t.month() as u16,
+                t.day() as u16,
+                t.year() as u16,
+                t.hour() as u16,
+                t.minute() as u16,
+                t.second() as u16,
+            ],
+            None => [0; 6],
+        };
+        for v in timestamp {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        write_fixed_str(out, &self.job_name, 41)?;
+        // job time, then software id and an empty software version
+        out.write_all(&[0; 6])?;
+        write_fixed_str(out, &self.software_id, 41)?;
+        out.write_all(&[0, 0, b' '])?;
+        // key color and pixel aspect ratio
+        out.write_all(&[0; 8])?;
+        let gamma = match self.gamma {
+            Some(g) => [(g * 1000.).round() as u16, 1000],
+            None => [0, 0],
+        };
+        out.write_all(&gamma[0].to_le_bytes())?;
+        out.write_all(&gamma[1].to_le_bytes())?;
+        // color correction table, postage stamp and scan line table offsets
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&stamp_offset.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        let alpha_type =
+            self.alpha_type
+                .unwrap_or(if bytespp == TGAFormat::RGBA as i32 || bytespp == 2 {
+                    TGAAlphaType::Alpha
+                } else {
+                    TGAAlphaType::NoAlpha
+                });
+        out.write_all(&[alpha_type as u8])?;
+
+        out.write_all(&extension_offset.to_le_bytes())?;
+        out.write_all(&developer_offset.to_le_bytes())?;
+        out.write_all(FOOTER_SIGNATURE)?;
+        Ok(())
+    }
+}
+
+/// Expands a little-endian A1R5G5B5 pixel to 8-bit BGRA.
+fn unpack_a1r5g5b5(p: [u8; 2]) -> [u8; 4] {
+    let v = u16::from_le_bytes(p);
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    [
+        expand(v & 0x1f),
+        expand((v >> 5) & 0x1f),
+        expand((v >> 10) & 0x1f),
+        if v & 0x8000 != 0 { 255 } else { 0 },
+    ]
+}
+
+fn pack_a1r5g5b5(bgra: [u8; 4]) -> [u8; 2] {
+    let v = (bgra[0] as u16 >> 3)
+        | ((bgra[1] as u16 >> 3) << 5)
+        | ((bgra[2] as u16 >> 3) << 10)
+        | (((bgra[3] >= 128) as u16) << 15);
+    v.to_le_bytes()
+}
+
+fn parse_fixed_str(b: &[u8]) -> String {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    String::from_utf8_lossy(&b[..end]).into_owned()
+}
+
+fn write_fixed_str(out: &mut impl Write, s: &str, len: usize) -> io::Result<()> {
+    let mut buf = vec![0u8; len];
+    let n = s.len().min(len - 1);
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+    out.write_all(&buf)
+}
+
+/// Builds a date from the month, day, year, hour, minute, second fields of the extension area.
+fn parse_timestamp(v: [u16; 6]) -> Option<PrimitiveDateTime> {
+    let month = Month::try_from(v[0] as u8).ok()?;
+    let date = Date::from_calendar_date(v[2] as i32, month, v[1] as u8).ok()?;
+    let time = Time::from_hms(v[3] as u8, v[4] as u8, v[5] as u8).ok()?;
+    Some(PrimitiveDateTime::new(date, time))
+}
+
 #[derive(Debug)]
 pub struct TGAColor {
     pub bgra: [u8; 4],
     pub bytespp: u8,
 }
 
+impl Default for TGAColor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TGAColor {
     pub fn new() -> Self {
         Self {
@@ -62,13 +656,8 @@ impl TGAColor {
 
     pub fn new_raw(p: &[u8], bpp: u8) -> Self {
         let mut bgra = [0; 4];
-        for i in 0..bpp as usize {
-            bgra[i] = p[i];
-        }
-        Self {
-            bgra: bgra,
-            bytespp: bpp,
-        }
+        bgra[..bpp as usize].copy_from_slice(&p[..bpp as usize]);
+        Self { bgra, bytespp: bpp }
     }
 }
 
@@ -92,11 +681,19 @@ pub enum TGAFormat {
     RGBA = 4,
 }
 
+#[derive(Clone)]
 pub struct TGAImage {
     data: Option<Vec<u8>>,
     width: i32,
     height: i32,
     bytespp: i32,
+    metadata: TGAMetadata,
+}
+
+impl Default for TGAImage {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TGAImage {
@@ -106,6 +703,7 @@ impl TGAImage {
             width: 0,
             height: 0,
             bytespp: 0,
+            metadata: TGAMetadata::default(),
         }
     }
 
@@ -115,42 +713,147 @@ impl TGAImage {
             width: w,
             height: h,
             bytespp: bpp,
+            metadata: TGAMetadata::default(),
         }
     }
 
-    pub fn read_tga_file(&mut self, filename: &str) -> io::Result<()> {
-        let mut f = BufReader::new(File::open(filename)?);
+    pub fn read_tga_file(&mut self, filename: &str) -> Result<(), TgaError> {
+        *self = TGAImage::from_reader(File::open(filename)?)?;
+        Ok(())
+    }
 
+    /// Decodes a TGA image from any reader. The whole input is buffered, since the
+    /// TGA 2.0 footer has to be read from the end of the data.
+    pub fn from_reader<R: Read>(reader: R) -> Result<TGAImage, TgaError> {
+        TGAImage::from_reader_with_limits(reader, DecodeLimits::default())
+    }
+
+    pub fn from_reader_with_limits<R: Read>(
+        mut reader: R,
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, TgaError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        TGAImage::from_bytes_with_limits(&bytes, limits)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<TGAImage, TgaError> {
+        TGAImage::from_bytes_with_limits(bytes, DecodeLimits::default())
+    }
+
+    pub fn from_bytes_with_limits(
+        bytes: &[u8],
+        limits: DecodeLimits,
+    ) -> Result<TGAImage, TgaError> {
+        let mut img = TGAImage::new();
+        img.decode(&mut TgaReader::new(Cursor::new(bytes)), limits)?;
+        Ok(img)
+    }
+
+    fn decode<R: Read + Seek>(
+        &mut self,
+        f: &mut TgaReader<R>,
+        limits: DecodeLimits,
+    ) -> Result<(), TgaError> {
         let header = TGAHeader {
             id_length: f.read_u8()?,
             color_map_type: f.read_u8()?,
             data_type_code: f.read_u8()?,
-            color_map_origin: f.read_u16::<LittleEndian>()?,
-            color_map_length: f.read_u16::<LittleEndian>()?,
+            color_map_origin: f.read_u16()?,
+            color_map_length: f.read_u16()?,
             color_map_depth: f.read_u8()?,
-            x_origin: f.read_u16::<LittleEndian>()?,
-            y_origin: f.read_u16::<LittleEndian>()?,
-            width: f.read_u16::<LittleEndian>()?,
-            height: f.read_u16::<LittleEndian>()?,
+            x_origin: f.read_u16()?,
+            y_origin: f.read_u16()?,
+            width: f.read_u16()?,
+            height: f.read_u16()?,
             bits_per_pixel: f.read_u8()?,
             image_descriptor: f.read_u8()?,
         };
 
+        let color_mapped = header.data_type_code == 1 || header.data_type_code == 9;
+        let grayscale = header.data_type_code == 3 || header.data_type_code == 11;
+        let rle = header.data_type_code >= 9 && header.data_type_code <= 11;
+        if !matches!(header.data_type_code, 1 | 2 | 3 | 9 | 10 | 11) {
+            return Err(TgaError::UnsupportedType {
+                type_code: header.data_type_code,
+            });
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(TgaError::BadDimensions {
+                width: header.width,
+                height: header.height,
+            });
+        }
+        // every supported format decodes to at most 4 bytes per pixel
+        let pixelcount = (header.width as usize).checked_mul(header.height as usize);
+        let fits = pixelcount
+            .and_then(|n| n.checked_mul(TGAFormat::RGBA as usize))
+            .is_some_and(|n| n <= i32::MAX as usize);
+        if header.width > limits.max_width || header.height > limits.max_height || !fits {
+            return Err(TgaError::TooLarge {
+                width: header.width,
+                height: header.height,
+            });
+        }
+        let pixelcount = pixelcount.unwrap();
+
+        let mut id = vec![0; header.id_length as usize];
+        f.read_exact(&mut id)?;
+        // true-color images may carry a palette too, which is of no use here
+        let color_map = if header.color_map_type == 1 && color_mapped {
+            Some(ColorMap::read(f, &header)?)
+        } else {
+            if header.color_map_type == 1 {
+                let entry_bytes = (header.color_map_depth as u64 + 7) >> 3;
+                f.skip(header.color_map_length as u64 * entry_bytes)?;
+            }
+            None
+        };
+        if color_mapped && color_map.is_none() {
+            return Err(TgaError::MissingColorMap);
+        }
+
         self.width = header.width as i32;
         self.height = header.height as i32;
-        self.bytespp = header.bits_per_pixel as i32 >> 3;
+        self.bytespp = (header.bits_per_pixel as i32 + 7) >> 3;
+        let valid_bpp = if color_mapped {
+            matches!(header.bits_per_pixel, 8 | 16)
+        } else if grayscale {
+            header.bits_per_pixel == 8
+        } else {
+            matches!(header.bits_per_pixel, 15 | 16 | 24 | 32)
+        };
+        if !valid_bpp {
+            return Err(TgaError::BadBitDepth {
+                bits: header.bits_per_pixel,
+            });
+        }
 
-        self.data = Some(vec![0; (self.bytespp * self.width * self.height) as usize]);
+        // refuse to allocate more than the remaining input can possibly fill,
+        // RLE packets holding at most 128 pixels each
+        let bytespp = self.bytespp as usize;
+        let min_bytes = if rle {
+            pixelcount.div_ceil(128) * (1 + bytespp)
+        } else {
+            pixelcount * bytespp
+        };
+        if f.remaining()? < min_bytes as u64 {
+            return Err(TgaError::Truncated { offset: f.offset });
+        }
+        self.data = Some(vec![0; pixelcount * bytespp]);
 
-        if header.data_type_code == 3 || header.data_type_code == 2 {
-            todo!()
-        } else if header.data_type_code == 10 || header.data_type_code == 11 {
-            if !self.load_rle_data(&mut f) {
-                eprintln!("Error occured while reading the data");
-                return Err(Error::last_os_error());
-            }
+        if rle {
+            self.load_rle_data(f)?;
         } else {
-            todo!()
+            f.read_exact(self.data.as_mut().unwrap())?;
+        }
+
+        let file_bytespp = self.bytespp;
+        let alpha_bit = header.image_descriptor & 0x0f != 0;
+        if let Some(color_map) = &color_map {
+            self.expand_color_map(color_map)?;
+        } else if self.bytespp == 2 {
+            self.expand_a1r5g5b5(alpha_bit);
         }
 
         if header.image_descriptor & 0x20 == 0 {
@@ -160,77 +863,118 @@ impl TGAImage {
             self.flip_horizontally();
         }
 
+        let stamp_bytespp = if color_mapped {
+            None
+        } else {
+            Some(file_bytespp)
+        };
+        self.metadata = TGAMetadata::read(f, stamp_bytespp, header.image_descriptor);
+
         Ok(())
     }
 
-    pub fn load_rle_data(&mut self, f: &mut BufReader<File>) -> bool {
-        let pixelcount = (self.width * self.height) as u32;
-        let mut currentpixel = 0 as u32;
-        let mut currentbyte = 0 as u32;
-        let mut colorbuffer = TGAColor::new();
-        loop {
-            let mut chunkheader;
-            match f.read_u8() {
-                Ok(val) => chunkheader = val,
-                Err(e) => {
-                    eprintln!("Error occured while reading the data, {e:?}");
-                    return false;
-                }
+    /// Replaces the color map indices in `data` with the palette entries they refer to.
+    fn expand_color_map(&mut self, color_map: &ColorMap) -> Result<(), TgaError> {
+        let index_bytes = self.bytespp as usize;
+        let indices = self.data.take().unwrap();
+        let mut data = Vec::with_capacity(indices.len() / index_bytes * color_map.bytespp);
+        for p in indices.chunks_exact(index_bytes) {
+            let index = if index_bytes == 2 {
+                u16::from_le_bytes([p[0], p[1]]) as usize
+            } else {
+                p[0] as usize
+            };
+            match color_map.entry(index) {
+                Some(entry) => data.extend_from_slice(entry),
+                None => return Err(TgaError::BadColorMapIndex { index }),
             }
-            if chunkheader < 128 {
-                chunkheader += 1;
-                for _ in 0..chunkheader {
-                    if let Err(e) = f.read_exact(&mut colorbuffer.bgra[0..self.bytespp as usize]) {
-                        eprintln!("Error occured while reading the data, {e:?}");
-                        return false;
-                    }
-                    for t in 0..self.bytespp {
-                        self.data.as_mut().unwrap()[currentbyte as usize] =
-                            colorbuffer.bgra[t as usize];
-                        currentbyte += 1;
-                    }
-                    currentpixel += 1;
-                    if currentpixel > pixelcount {
-                        eprintln!("Too many pixels read");
-                        return false;
-                    }
-                }
+        }
+        self.data = Some(data);
+        self.bytespp = color_map.bytespp as i32;
+        Ok(())
+    }
+
+    /// Converts packed 16-bit pixels to RGB, or to RGBA when the attribute bit holds alpha.
+    fn expand_a1r5g5b5(&mut self, alpha: bool) {
+        let bytespp = if alpha {
+            TGAFormat::RGBA as usize
+        } else {
+            TGAFormat::RGB as usize
+        };
+        let packed = self.data.take().unwrap();
+        let mut data = Vec::with_capacity(packed.len() / 2 * bytespp);
+        for p in packed.chunks_exact(2) {
+            data.extend_from_slice(&unpack_a1r5g5b5([p[0], p[1]])[..bytespp]);
+        }
+        self.data = Some(data);
+        self.bytespp = bytespp as i32;
+    }
+
+    /// Packs the image into 16-bit A1R5G5B5 pixels; alpha is thresholded at 128.
+    fn to_a1r5g5b5(&self) -> TGAImage {
+        let mut packed = TGAImage::new_dimensions(self.width, self.height, 2);
+        let src = self.data.as_ref().unwrap();
+        let dst = packed.data.as_mut().unwrap();
+        for (i, p) in src.chunks_exact(self.bytespp as usize).enumerate() {
+            let bgra = match p.len() {
+                1 => [p[0], p[0], p[0], 255],
+                3 => [p[0], p[1], p[2], 255],
+                _ => [p[0], p[1], p[2], p[3]],
+            };
+            dst[i * 2..i * 2 + 2].copy_from_slice(&pack_a1r5g5b5(bgra));
+        }
+        packed.metadata = self.metadata.clone();
+        if let Some(stamp) = &self.metadata.postage_stamp {
+            packed.metadata.postage_stamp = Some(Box::new(stamp.to_a1r5g5b5()));
+        }
+        packed
+    }
+
+    /// Writes the image as 16-bit A1R5G5B5 for memory-constrained texture targets.
+    pub fn write_tga_file_a1r5g5b5(&self, filename: &str, rle: bool) -> io::Result<()> {
+        self.to_a1r5g5b5().write_tga_file(filename, rle)
+    }
+
+    fn load_rle_data<R: Read>(&mut self, f: &mut TgaReader<R>) -> Result<(), TgaError> {
+        let bytespp = self.bytespp as usize;
+        let pixelcount = (self.width * self.height) as usize;
+        let data = self.data.as_mut().unwrap();
+        let mut currentpixel = 0;
+        let mut colorbuffer = [0u8; 4];
+        while currentpixel < pixelcount {
+            let offset = f.offset;
+            let chunkheader = f.read_u8()?;
+            let raw = chunkheader < 128;
+            let count = if raw {
+                chunkheader as usize + 1
             } else {
-                chunkheader -= 127;
-                if let Err(e) = f.read_exact(&mut colorbuffer.bgra[0..self.bytespp as usize]) {
-                    eprintln!("Error occured while reading the data, {e:?}");
-                    return false;
-                }
-                for _ in 0..chunkheader {
-                    for t in 0..self.bytespp {
-                        self.data.as_mut().unwrap()[currentbyte as usize] =
-                            colorbuffer.bgra[t as usize];
-                        currentbyte += 1;
-                    }
-                    currentpixel += 1;
-                    if currentpixel > pixelcount {
-                        eprintln!("Too many pixels read");
-                        return false;
-                    }
-                }
+                chunkheader as usize - 127
+            };
+            if currentpixel + count > pixelcount {
+                return Err(TgaError::PixelOverflow { offset });
             }
-            if !(currentpixel < pixelcount) {
-                break;
+            let chunk = &mut data[currentpixel * bytespp..(currentpixel + count) * bytespp];
+            if raw {
+                f.read_exact(chunk)?;
+            } else {
+                f.read_exact(&mut colorbuffer[..bytespp])?;
+                for pixel in chunk.chunks_exact_mut(bytespp) {
+                    pixel.copy_from_slice(&colorbuffer[..bytespp]);
+                }
             }
+            currentpixel += count;
         }
-        true
+        Ok(())
     }
 
     pub fn write_tga_file(&self, filename: &str, rle: bool) -> io::Result<()> {
-        let developer_area_ref: [u8; 4] = [0, 0, 0, 0];
-        let extension_area_ref: [u8; 4] = [0, 0, 0, 0];
-
-        // let mut footer: &[char] = &[
-        //     'T', 'R', 'U', 'E', 'V', 'I', 'S', 'I', 'O', 'N', '-', 'X', 'F', 'I', 'L', 'E', '.',
-        //     '\0',
-        // ];
+        let mut out = BufWriter::new(File::create(filename)?);
+        self.write_to(&mut out, rle)?;
+        out.flush()
+    }
 
-        let mut out = File::create(filename)?;
+    pub fn write_to<W: Write>(&self, out: W, rle: bool) -> io::Result<()> {
+        let mut out = TgaWriter::new(out);
         let mut header = TGAHeader::new();
         header.bits_per_pixel = (self.bytespp as u8) << 3;
         header.width = self.width as u16;
@@ -248,26 +992,110 @@ impl TGAImage {
                 2
             }
         };
-        header.image_descriptor = 0x20;
-
-        out.write(&header.id_length.to_le_bytes())?;
-        out.write(&header.color_map_type.to_le_bytes())?;
-        out.write(&header.data_type_code.to_le_bytes())?;
-        out.write(&header.color_map_origin.to_le_bytes())?;
-        out.write(&header.color_map_length.to_le_bytes())?;
-        out.write(&header.color_map_depth.to_le_bytes())?;
-        out.write(&header.x_origin.to_le_bytes())?;
-        out.write(&header.y_origin.to_le_bytes())?;
-        out.write(&header.width.to_le_bytes())?;
-        out.write(&header.height.to_le_bytes())?;
-        out.write(&header.bits_per_pixel.to_le_bytes())?;
-        out.write(&header.image_descriptor.to_le_bytes())?;
-
-        out.write(self.data.as_ref().unwrap().as_slice())?;
+        // top-left origin, plus the number of attribute (alpha) bits per pixel
+        header.image_descriptor = 0x20
+            | match self.bytespp {
+                2 => 1,
+                4 => 8,
+                _ => 0,
+            };
+
+        out.write_all(&header.id_length.to_le_bytes())?;
+        out.write_all(&header.color_map_type.to_le_bytes())?;
+        out.write_all(&header.data_type_code.to_le_bytes())?;
+        out.write_all(&header.color_map_origin.to_le_bytes())?;
+        out.write_all(&header.color_map_length.to_le_bytes())?;
+        out.write_all(&header.color_map_depth.to_le_bytes())?;
+        out.write_all(&header.x_origin.to_le_bytes())?;
+        out.write_all(&header.y_origin.to_le_bytes())?;
+        out.write_all(&header.width.to_le_bytes())?;
+        out.write_all(&header.height.to_le_bytes())?;
+        out.write_all(&header.bits_per_pixel.to_le_bytes())?;
+        out.write_all(&header.image_descriptor.to_le_bytes())?;
+
+        if rle {
+            self.unload_rle_data(&mut out)?;
+        } else {
+            out.write_all(self.data.as_ref().unwrap().as_slice())?;
+        }
+        self.metadata.write(&mut out, self.bytespp)
+    }
 
+    pub fn unload_rle_data<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        const MAX_CHUNK_LENGTH: usize = 128;
+        let data = self.data.as_ref().unwrap().as_slice();
+        let bytespp = self.bytespp as usize;
+        let npixels = (self.width * self.height) as usize;
+        let mut curpix = 0;
+        while curpix < npixels {
+            let chunkstart = curpix * bytespp;
+            let mut curbyte = curpix * bytespp;
+            let mut run_length = 1;
+            let mut raw = true;
+            while curpix + run_length < npixels && run_length < MAX_CHUNK_LENGTH {
+                let succ_eq = data[curbyte..curbyte + bytespp]
+                    == data[curbyte + bytespp..curbyte + 2 * bytespp];
+                curbyte += bytespp;
+                if run_length == 1 {
+                    raw = !succ_eq;
+                }
+                if raw && succ_eq {
+                    run_length -= 1;
+                    break;
+                }
+                if !raw && !succ_eq {
+                    break;
+                }
+                run_length += 1;
+            }
+            curpix += run_length;
+            if raw {
+                out.write_all(&[(run_length - 1) as u8])?;
+                out.write_all(&data[chunkstart..chunkstart + run_length * bytespp])?;
+            } else {
+                out.write_all(&[(run_length + 127) as u8])?;
+                out.write_all(&data[chunkstart..chunkstart + bytespp])?;
+            }
+        }
         Ok(())
     }
 
+    pub fn metadata(&self) -> &TGAMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut TGAMetadata {
+        &mut self.metadata
+    }
+
+    /// Nearest-neighbour downscale that fits in 64x64, as recommended for TGA postage stamps.
+    pub fn postage_stamp(&self) -> TGAImage {
+        let scale = (self.width.max(self.height) as f32 / 64.).max(1.);
+        let w = ((self.width as f32 / scale) as i32).max(1);
+        let h = ((self.height as f32 / scale) as i32).max(1);
+        let mut stamp = TGAImage::new_dimensions(w, h, self.bytespp);
+        for y in 0..h {
+            for x in 0..w {
+                let c = self.get((x as f32 * scale) as i32, (y as f32 * scale) as i32);
+                stamp.set(x, y, &c);
+            }
+        }
+        stamp
+    }
+
+    pub fn get_bytespp(&self) -> i32 {
+        self.bytespp
+    }
+
+    /// Raw pixel data, row by row from the top, in BGR(A) order.
+    pub fn buffer(&self) -> Option<&[u8]> {
+        self.data.as_deref()
+    }
+
+    pub fn buffer_mut(&mut self) -> Option<&mut [u8]> {
+        self.data.as_deref_mut()
+    }
+
     pub fn get_width(&self) -> i32 {
         self.width
     }
@@ -292,10 +1120,10 @@ impl TGAImage {
         }
 
         let index = ((x + y * self.width) * self.bytespp) as usize;
-        return TGAColor::new_raw(
+        TGAColor::new_raw(
             &self.data.as_ref().unwrap().as_slice()[index..index + self.bytespp as usize],
             self.bytespp as u8,
-        );
+        )
     }
 
     pub fn flip_vertically(&mut self) -> bool {
@@ -307,7 +1135,7 @@ impl TGAImage {
         let half = self.height >> 1;
         for i in 0..half as usize {
             // TODO: change self.{height, width, length, ...} to usize
-            let l1 = (i * bytes_per_line) as usize;
+            let l1 = i * bytes_per_line;
             let l2 = (self.height as usize - 1 - i) * bytes_per_line;
             line.as_mut_slice()
                 .copy_from_slice(&self.data.as_ref().unwrap().as_slice()[l1..l1 + bytes_per_line]);
@@ -319,6 +1147,502 @@ impl TGAImage {
     }
 
     pub fn flip_horizontally(&mut self) -> bool {
-        todo!()
+        if self.data.is_none() {
+            return false;
+        }
+        let bytespp = self.bytespp as usize;
+        let bytes_per_line = self.width as usize * bytespp;
+        let half = (self.width >> 1) as usize;
+        for line in self.data.as_mut().unwrap().chunks_exact_mut(bytes_per_line) {
+            for i in 0..half {
+                let p1 = i * bytespp;
+                let p2 = (self.width as usize - 1 - i) * bytespp;
+                for b in 0..bytespp {
+                    line.swap(p1 + b, p2 + b);
+                }
+            }
+        }
+        true
+    }
+
+    /// Swaps rows and columns, so that pixel (x, y) ends up at (y, x).
+    pub fn transpose(&mut self) -> bool {
+        if self.data.is_none() {
+            return false;
+        }
+        let bytespp = self.bytespp as usize;
+        let (w, h) = (self.width as usize, self.height as usize);
+        let src = self.data.as_ref().unwrap();
+        let mut dst = vec![0; src.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let from = (x + y * w) * bytespp;
+                let to = (y + x * h) * bytespp;
+                dst[to..to + bytespp].copy_from_slice(&src[from..from + bytespp]);
+            }
+        }
+        self.data = Some(dst);
+        std::mem::swap(&mut self.width, &mut self.height);
+        true
+    }
+
+    /// Rotates the image by 90 degrees clockwise, with the origin in the top-left corner.
+    pub fn rotate_90(&mut self) -> bool {
+        self.transpose() && self.flip_horizontally()
+    }
+
+    pub fn rotate_180(&mut self) -> bool {
+        self.flip_vertically() && self.flip_horizontally()
+    }
+
+    /// Rotates the image by 90 degrees counterclockwise, with the origin in the top-left corner.
+    pub fn rotate_270(&mut self) -> bool {
+        self.transpose() && self.flip_vertically()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(img: &TGAImage, name: &str, rle: bool) -> TGAImage {
+        let path = std::env::temp_dir().join(format!("tinyrender_{}_{}.tga", name, rle));
+        let path = path.to_str().unwrap();
+        img.write_tga_file(path, rle).unwrap();
+        let mut read = TGAImage::new();
+        read.read_tga_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        read
+    }
+
+    fn test_image(w: i32, h: i32, bpp: i32) -> TGAImage {
+        let mut img = TGAImage::new_dimensions(w, h, bpp);
+        for y in 0..h {
+            for x in 0..w {
+                // long uniform runs on the top half, noise-like raw runs below
+                let v = if y < h / 2 {
+                    (x / 150) as u8
+                } else {
+                    (x * 7 + y * 13) as u8
+                };
+                let c = TGAColor::new_rgba(v, v.wrapping_add(1), v.wrapping_mul(3), 255 - v);
+                img.set(x, y, &c);
+            }
+        }
+        img
+    }
+
+    fn assert_same(a: &TGAImage, b: &TGAImage) {
+        assert_eq!(a.width, b.width);
+        assert_eq!(a.height, b.height);
+        assert_eq!(a.bytespp, b.bytespp);
+        assert_eq!(a.data, b.data);
+    }
+
+    #[test]
+    fn rle_round_trip_grayscale() {
+        let img = test_image(300, 20, TGAFormat::Grayscale as i32);
+        assert_same(&img, &round_trip(&img, "gray", true));
+    }
+
+    #[test]
+    fn rle_round_trip_rgb() {
+        let img = test_image(300, 20, TGAFormat::RGB as i32);
+        assert_same(&img, &round_trip(&img, "rgb", true));
+    }
+
+    #[test]
+    fn rle_round_trip_rgba() {
+        let img = test_image(300, 20, TGAFormat::RGBA as i32);
+        assert_same(&img, &round_trip(&img, "rgba", true));
+        assert_same(&img, &round_trip(&img, "rgba", false));
+    }
+
+    #[test]
+    fn uncompressed_round_trip() {
+        for &bpp in &[1, 3, 4] {
+            let path = std::env::temp_dir().join(format!("tinyrender_raw_{}.tga", bpp));
+            let path = path.to_str().unwrap();
+            let mut img = TGAImage::new_dimensions(5, 3, bpp);
+            for y in 0..3 {
+                for x in 0..5 {
+                    let v = (x * 40 + y * 7) as u8;
+                    img.set(x, y, &TGAColor::new_rgba(v, v ^ 0x55, 255 - v, v / 2));
+                }
+            }
+            img.write_tga_file(path, false).unwrap();
+            let mut read = TGAImage::new();
+            read.read_tga_file(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            assert_eq!((read.get_width(), read.get_height()), (5, 3));
+            for y in 0..3 {
+                for x in 0..5 {
+                    let (a, b) = (img.get(x, y), read.get(x, y));
+                    assert_eq!(b.bytespp as i32, bpp);
+                    assert_eq!(
+                        a.bgra[..bpp as usize],
+                        b.bgra[..bpp as usize],
+                        "{} {}",
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uncompressed_true_color_and_grayscale() {
+        #[rustfmt::skip]
+        let bytes = [
+            // 2x2 24-bit true color, bottom row first
+            0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0,
+            1, 2, 3, 4, 5, 6,
+            7, 8, 9, 10, 11, 12,
+        ];
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!((img.width, img.height, img.bytespp), (2, 2, 3));
+        assert_eq!(img.data, Some(vec![7, 8, 9, 10, 11, 12, 1, 2, 3, 4, 5, 6]));
+        assert!(matches!(
+            TGAImage::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(TgaError::Truncated { offset: 18 })
+        ));
+
+        #[rustfmt::skip]
+        let bytes = [
+            // 3x1 grayscale, top row first and right to left
+            0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 1, 0, 8, 0x30,
+            10, 20, 30,
+        ];
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.bytespp, TGAFormat::Grayscale as i32);
+        assert_eq!(img.data, Some(vec![30, 20, 10]));
+    }
+
+    #[test]
+    fn broken_metadata_is_skipped() {
+        #[rustfmt::skip]
+        let image = [
+            0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 8, 0x20,
+            10, 20,
+        ];
+        let with_footer = |extension: u32, developer: u32, extra: &[u8]| {
+            let mut bytes = image.to_vec();
+            bytes.extend_from_slice(extra);
+            bytes.extend_from_slice(&extension.to_le_bytes());
+            bytes.extend_from_slice(&developer.to_le_bytes());
+            bytes.extend_from_slice(FOOTER_SIGNATURE);
+            bytes
+        };
+        let decode = |bytes: &[u8]| {
+            let img = TGAImage::from_bytes(bytes).unwrap();
+            assert_eq!(img.data, Some(vec![10, 20]));
+            img.metadata.warnings
+        };
+
+        // both areas past the end of the input
+        assert_eq!(decode(&with_footer(1000, 2000, &[])).len(), 2);
+        // an extension area cut short, whose size field claims the full area
+        let ext_size = EXTENSION_AREA_SIZE.to_le_bytes();
+        assert_eq!(decode(&with_footer(20, 0, &ext_size)).len(), 1);
+        // a developer directory with a field reaching past the end
+        #[rustfmt::skip]
+        let directory = [1, 0, 7, 0, 20, 0, 0, 0, 0xff, 0, 0, 0];
+        let warnings = decode(&with_footer(0, 20, &directory));
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("developer area"), "{:?}", warnings);
+        // and nothing to complain about without a footer
+        assert!(decode(&image).is_empty());
+    }
+
+    #[test]
+    fn color_mapped_uncompressed() {
+        #[rustfmt::skip]
+        let bytes = [
+            // 3 byte image ID, color map of 2 24-bit entries starting at index 1
+            3, 1, 1, 1, 0, 2, 0, 24, 0, 0, 0, 0, 2, 0, 2, 0, 8, 0x20,
+            b'a', b'b', b'c',
+            10, 20, 30, 40, 50, 60,
+            1, 2,
+            2, 1,
+        ];
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.bytespp, TGAFormat::RGB as i32);
+        assert_eq!(
+            img.data,
+            Some(vec![10, 20, 30, 40, 50, 60, 40, 50, 60, 10, 20, 30])
+        );
+    }
+
+    #[test]
+    fn color_mapped_rle_16bit_palette() {
+        #[rustfmt::skip]
+        let bytes = [
+            0, 1, 9, 0, 0, 2, 0, 16, 0, 0, 0, 0, 3, 0, 1, 0, 8, 0x20,
+            // pure blue and pure red in A1R5G5B5
+            0x1f, 0x00, 0x00, 0x7c,
+            // repeat packet of 2 x index 1, raw packet of 1 x index 0
+            0x81, 1, 0x00, 0,
+        ];
+        // no attribute bits in the descriptor, so the entries are opaque
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.bytespp, TGAFormat::RGBA as i32);
+        assert_eq!(
+            img.data,
+            Some(vec![0, 0, 255, 255, 0, 0, 255, 255, 255, 0, 0, 255])
+        );
+
+        let mut bad_index = bytes;
+        bad_index[bytes.len() - 1] = 5;
+        assert!(matches!(
+            TGAImage::from_bytes(&bad_index),
+            Err(TgaError::BadColorMapIndex { index: 5 })
+        ));
+    }
+
+    #[test]
+    fn color_mapped_16bit_palette_alpha() {
+        #[rustfmt::skip]
+        let bytes = [
+            // one attribute bit per pixel
+            0, 1, 1, 0, 0, 2, 0, 16, 0, 0, 0, 0, 2, 0, 1, 0, 8, 0x21,
+            // transparent green and opaque red in A1R5G5B5
+            0xe0, 0x03, 0x00, 0xfc,
+            0, 1,
+        ];
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.bytespp, TGAFormat::RGBA as i32);
+        assert_eq!(img.data, Some(vec![0, 255, 0, 0, 0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn true_color_skips_palette() {
+        #[rustfmt::skip]
+        let bytes = [
+            // 24-bit true color with a palette of 3 12-bit entries, which only needs skipping
+            0, 1, 2, 0, 0, 3, 0, 12, 0, 0, 0, 0, 1, 0, 1, 0, 24, 0x20,
+            1, 2, 3, 4, 5, 6,
+            7, 8, 9,
+        ];
+        let img = TGAImage::from_bytes(&bytes).unwrap();
+        assert_eq!(img.data, Some(vec![7, 8, 9]));
+        assert!(matches!(
+            TGAImage::from_bytes(&bytes[..21]),
+            Err(TgaError::Truncated { offset: 21 })
+        ));
+    }
+
+    #[test]
+    fn metadata_round_trip() {
+        let mut img = test_image(100, 40, TGAFormat::RGBA as i32);
+        let stamp = img.postage_stamp();
+        assert_eq!((stamp.width, stamp.height), (64, 25));
+        let timestamp = PrimitiveDateTime::new(
+            Date::from_calendar_date(2022, Month::June, 3).unwrap(),
+            Time::from_hms(13, 37, 5).unwrap(),
+        );
+        {
+            let metadata = img.metadata_mut();
+            metadata.author = String::from("tinyrender");
+            metadata.comments = String::from("spp=1\nshading=phong");
+            metadata.timestamp = Some(timestamp);
+            metadata.job_name = String::from("african_head");
+            metadata.software_id = String::from("tinyrender 0.1.0");
+            metadata.gamma = Some(2.2);
+            metadata.alpha_type = Some(TGAAlphaType::Premultiplied);
+            metadata.postage_stamp = Some(Box::new(stamp.clone()));
+            metadata.developer_fields = vec![
+                TGADeveloperField {
+                    tag: 1,
+                    data: b"eye=1,1,3".to_vec(),
+                },
+                TGADeveloperField {
+                    tag: 7,
+                    data: vec![],
+                },
+            ];
+        }
+
+        for &rle in &[false, true] {
+            let read = round_trip(&img, "metadata", rle);
+            assert_same(&img, &read);
+            let metadata = read.metadata();
+            assert_eq!(metadata.author, "tinyrender");
+            assert_eq!(metadata.comments, "spp=1\nshading=phong");
+            assert_eq!(metadata.timestamp, Some(timestamp));
+            assert_eq!(metadata.job_name, "african_head");
+            assert_eq!(metadata.software_id, "tinyrender 0.1.0");
+            assert_eq!(metadata.gamma, Some(2.2));
+            assert_eq!(metadata.alpha_type, Some(TGAAlphaType::Premultiplied));
+            assert_eq!(metadata.developer_fields, img.metadata().developer_fields);
+            assert_same(metadata.postage_stamp.as_ref().unwrap(), &stamp);
+        }
+    }
+
+    #[test]
+    fn a1r5g5b5_round_trip() {
+        let mut img = test_image(300, 20, TGAFormat::RGBA as i32);
+        img.metadata_mut().postage_stamp = Some(Box::new(img.postage_stamp()));
+        let path = std::env::temp_dir().join("tinyrender_a1r5g5b5.tga");
+        let path = path.to_str().unwrap();
+        img.write_tga_file_a1r5g5b5(path, true).unwrap();
+        let mut read = TGAImage::new();
+        read.read_tga_file(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let quantize = |c: u8| (c & 0xf8) | (c >> 5);
+        let expected = |src: &TGAImage| {
+            let mut quantized = src.clone();
+            for p in quantized.data.as_mut().unwrap().chunks_exact_mut(4) {
+                for c in &mut p[..3] {
+                    *c = quantize(*c);
+                }
+                p[3] = if p[3] >= 128 { 255 } else { 0 };
+            }
+            quantized
+        };
+        assert_same(&expected(&img), &read);
+        let stamp = img.metadata().postage_stamp.as_ref().unwrap();
+        assert_same(
+            &expected(stamp),
+            read.metadata().postage_stamp.as_ref().unwrap(),
+        );
+    }
+
+    #[test]
+    fn decoding_errors() {
+        #[rustfmt::skip]
+        let header = [0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0x20];
+        let rle = |packets: &[u8]| [&header[..], packets].concat();
+
+        assert!(matches!(
+            TGAImage::from_bytes(&header[..10]),
+            Err(TgaError::Truncated { offset: 10 })
+        ));
+        assert!(matches!(
+            TGAImage::from_bytes(&rle(&[0x81, 1, 2, 3, 0x00, 4, 5])),
+            Err(TgaError::Truncated { offset: 23 })
+        ));
+        assert!(matches!(
+            TGAImage::from_bytes(&rle(&[0x81, 1, 2, 3, 0x82, 4, 5, 6])),
+            Err(TgaError::PixelOverflow { offset: 22 })
+        ));
+
+        let mut bad = header;
+        bad[2] = 32;
+        assert!(matches!(
+            TGAImage::from_bytes(&bad),
+            Err(TgaError::UnsupportedType { type_code: 32 })
+        ));
+        let mut bad = header;
+        bad[12] = 0;
+        assert!(matches!(
+            TGAImage::from_bytes(&bad),
+            Err(TgaError::BadDimensions {
+                width: 0,
+                height: 2
+            })
+        ));
+        let mut bad = header;
+        bad[16] = 12;
+        assert!(matches!(
+            TGAImage::from_bytes(&bad),
+            Err(TgaError::BadBitDepth { bits: 12 })
+        ));
+    }
+
+    #[test]
+    fn decode_from_bytes() {
+        let bytes = include_bytes!("../obj/african_head/african_head_eye_outer_diffuse.tga");
+        let img = TGAImage::from_bytes(bytes).unwrap();
+        let mut from_file = TGAImage::new();
+        from_file
+            .read_tga_file("obj/african_head/african_head_eye_outer_diffuse.tga")
+            .unwrap();
+        assert_same(&img, &from_file);
+        assert_same(&img, &TGAImage::from_reader(&bytes[..]).unwrap());
+
+        let mut encoded = Vec::new();
+        img.write_to(&mut encoded, true).unwrap();
+        assert_same(&img, &TGAImage::from_bytes(&encoded).unwrap());
+    }
+
+    #[test]
+    fn hostile_headers() {
+        #[rustfmt::skip]
+        let header = [0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 32, 0x20];
+        assert!(matches!(
+            TGAImage::from_bytes(&header),
+            Err(TgaError::TooLarge { .. })
+        ));
+        let limits = DecodeLimits {
+            max_width: u16::MAX,
+            max_height: u16::MAX,
+        };
+        assert!(matches!(
+            TGAImage::from_bytes_with_limits(&header, limits),
+            Err(TgaError::TooLarge { .. })
+        ));
+
+        // a few bytes claiming to be a 16384x16384 RLE image
+        let mut header = header;
+        header[12..16].copy_from_slice(&[0, 0x40, 0, 0x40]);
+        let bytes = [&header[..], &[0xff, 1, 2, 3, 4]].concat();
+        assert!(matches!(
+            TGAImage::from_bytes(&bytes),
+            Err(TgaError::Truncated { offset: 18 })
+        ));
+        let small = DecodeLimits {
+            max_width: 1024,
+            max_height: 1024,
+        };
+        assert!(matches!(
+            TGAImage::from_bytes_with_limits(&bytes, small),
+            Err(TgaError::TooLarge {
+                width: 16384,
+                height: 16384
+            })
+        ));
+    }
+
+    #[test]
+    fn rotations_and_transpose() {
+        // 3x2 grayscale image:
+        //   0 1 2
+        //   3 4 5
+        let mut img = TGAImage::new_dimensions(3, 2, TGAFormat::Grayscale as i32);
+        img.data = Some(vec![0, 1, 2, 3, 4, 5]);
+
+        let mut flipped = TGAImage::new_dimensions(3, 2, TGAFormat::Grayscale as i32);
+        flipped.data = img.data.clone();
+        assert!(flipped.flip_horizontally());
+        assert_eq!(flipped.data, Some(vec![2, 1, 0, 5, 4, 3]));
+
+        assert!(img.transpose());
+        assert_eq!((img.width, img.height), (2, 3));
+        assert_eq!(img.data, Some(vec![0, 3, 1, 4, 2, 5]));
+        assert!(img.transpose());
+
+        assert!(img.rotate_90());
+        assert_eq!((img.width, img.height), (2, 3));
+        assert_eq!(img.data, Some(vec![3, 0, 4, 1, 5, 2]));
+        assert!(img.rotate_270());
+        assert_eq!(img.data, Some(vec![0, 1, 2, 3, 4, 5]));
+
+        assert!(img.rotate_180());
+        assert_eq!((img.width, img.height), (3, 2));
+        assert_eq!(img.data, Some(vec![5, 4, 3, 2, 1, 0]));
+    }
+
+    #[test]
+    fn rle_output_is_compressed() {
+        let img = TGAImage::new_dimensions(256, 256, TGAFormat::RGB as i32);
+        let path = std::env::temp_dir().join("tinyrender_rle_size.tga");
+        let path = path.to_str().unwrap();
+        img.write_tga_file(path, true).unwrap();
+        let len = std::fs::metadata(path).unwrap().len();
+        std::fs::remove_file(path).unwrap();
+        // 18 byte header, 512 repeat packets of 1 + 3 bytes, extension area and footer
+        assert_eq!(len, 18 + 512 * 4 + 495 + 26);
     }
 }